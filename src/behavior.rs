@@ -0,0 +1,327 @@
+use super::{rng::GameRng, Ability, Map};
+use rltk::{Algorithm2D, BaseMap, Point};
+use serde::{Deserialize, Serialize};
+
+/// What a monster does on its turn, built out of smaller behaviors in the raws
+/// Each behavior either picks an action or passes, letting the next one in a Priority try
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Behavior {
+    /// Use the first of these that picks an action
+    Priority(Vec<Behavior>),
+    /// Only try `then` while the condition holds
+    When {
+        condition: Condition,
+        then: Box<Behavior>,
+    },
+    /// Walk towards the target, attacking with the bump attack once next to it
+    Chase,
+    /// Use an attack on the target from up to `reach` tiles away
+    Shoot {
+        attack: String,
+        reach: i32,
+    },
+    /// Use the first ability in the moveset that is ready and can reach the target
+    UseAbilities,
+    /// Back off while the target is closer than `distance`
+    KeepDistance {
+        distance: i32,
+    },
+    /// Step away from the target, as long as that gets further from it
+    Flee,
+    /// Walk between points given relative to where the monster was placed
+    Patrol {
+        route: Vec<(i32, i32)>,
+    },
+    /// Chase anything that comes within `radius` of home, and walk back home otherwise
+    Guard {
+        radius: i32,
+    },
+    /// Head for where the target was last seen while it is out of sight
+    Investigate,
+    /// Use an attack centred on ourselves the moment the target is spotted, to alert others
+    Shout {
+        attack: String,
+    },
+    /// Stay within `distance` of the leader, for companions tagging along with the player
+    Follow {
+        distance: i32,
+    },
+    /// Stumble around at random
+    Wander,
+    Wait,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Condition {
+    SeesTarget,
+    /// Health is below this fraction of the maximum
+    HealthBelow(f32),
+    TargetWithin(i32),
+}
+
+pub enum AiAction {
+    Move(Point),
+    Attack {
+        loc: Point,
+        attack: String,
+    },
+    /// Use the ability in this slot of the moveset
+    UseAbility {
+        slot: usize,
+        loc: Point,
+    },
+    Wait,
+}
+
+/// Everything a behavior gets to look at when deciding what to do
+pub struct AiContext<'a> {
+    pub map: &'a Map,
+    pub pos: Point,
+    /// Where the target is, if it can be seen
+    pub target: Option<Point>,
+    /// Where the target was last known to be, which may be where it is now
+    pub last_seen: Option<Point>,
+    /// Whether the target came into view this turn without us knowing about it before
+    pub just_spotted: bool,
+    pub health_fraction: f32,
+    pub bump_attack: Option<&'a str>,
+    pub abilities: &'a [Ability],
+    pub home: Point,
+    /// Who we tag along with, if we are on the same side as the player
+    pub leader: Option<Point>,
+    pub patrol_step: &'a mut usize,
+    pub rng: &'a mut GameRng,
+}
+
+impl Condition {
+    fn holds(&self, ctx: &AiContext) -> bool {
+        match self {
+            Condition::SeesTarget => ctx.target.is_some(),
+            Condition::HealthBelow(fraction) => ctx.health_fraction < *fraction,
+            Condition::TargetWithin(distance) => ctx
+                .target
+                .is_some_and(|target| tile_distance(ctx.pos, target) <= *distance),
+        }
+    }
+}
+
+impl Behavior {
+    pub fn decide(&self, ctx: &mut AiContext) -> Option<AiAction> {
+        match self {
+            Behavior::Priority(children) => children.iter().find_map(|child| child.decide(ctx)),
+            Behavior::When { condition, then } => {
+                if condition.holds(ctx) {
+                    then.decide(ctx)
+                } else {
+                    None
+                }
+            }
+            Behavior::Chase => ctx.target.and_then(|target| approach(ctx, target)),
+            Behavior::Shoot { attack, reach } => {
+                let target = ctx.target?;
+                if target == ctx.pos || tile_distance(ctx.pos, target) > *reach {
+                    return None;
+                }
+
+                // hold fire rather than hit a wall or whoever is standing in the way
+                if !has_line_of_fire(ctx.map, ctx.pos, target) {
+                    return None;
+                }
+
+                Some(AiAction::Attack {
+                    loc: target,
+                    attack: attack.clone(),
+                })
+            }
+            Behavior::UseAbilities => {
+                let target = ctx.target?;
+                ctx.abilities
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, ability)| ability.is_ready())
+                    .find_map(|(slot, ability)| {
+                        aim_ability(ctx, ability, target)
+                            .map(|loc| AiAction::UseAbility { slot, loc })
+                    })
+            }
+            Behavior::KeepDistance { distance } => {
+                let target = ctx.target?;
+                if tile_distance(ctx.pos, target) >= *distance {
+                    return None;
+                }
+
+                step_away(ctx.map, ctx.pos, target).map(AiAction::Move)
+            }
+            Behavior::Flee => ctx
+                .target
+                .and_then(|target| step_away(ctx.map, ctx.pos, target))
+                .map(AiAction::Move),
+            Behavior::Patrol { route } => {
+                if route.is_empty() {
+                    return None;
+                }
+
+                // skip past waypoints we are standing on, or that can't be walked to
+                for _ in 0..route.len() {
+                    let (dx, dy) = route[*ctx.patrol_step % route.len()];
+                    let waypoint = ctx.home + Point::new(dx, dy);
+                    if waypoint != ctx.pos {
+                        if let Some(step) = step_towards(ctx.map, ctx.pos, waypoint) {
+                            return Some(AiAction::Move(step));
+                        }
+                    }
+
+                    *ctx.patrol_step = (*ctx.patrol_step + 1) % route.len();
+                }
+
+                None
+            }
+            Behavior::Guard { radius } => {
+                if let Some(target) = ctx.target {
+                    if tile_distance(ctx.home, target) <= *radius {
+                        return approach(ctx, target);
+                    }
+                }
+
+                if ctx.pos == ctx.home {
+                    return None;
+                }
+
+                step_towards(ctx.map, ctx.pos, ctx.home).map(AiAction::Move)
+            }
+            Behavior::Investigate => {
+                let last_seen = ctx.last_seen?;
+                if ctx.target.is_some() || last_seen == ctx.pos {
+                    return None;
+                }
+
+                step_towards(ctx.map, ctx.pos, last_seen).map(AiAction::Move)
+            }
+            Behavior::Shout { attack } => {
+                if !ctx.just_spotted {
+                    return None;
+                }
+
+                Some(AiAction::Attack {
+                    loc: ctx.pos,
+                    attack: attack.clone(),
+                })
+            }
+            Behavior::Follow { distance } => {
+                let leader = ctx.leader?;
+                if tile_distance(ctx.pos, leader) <= *distance {
+                    return None;
+                }
+
+                step_towards(ctx.map, ctx.pos, leader).map(AiAction::Move)
+            }
+            Behavior::Wander => {
+                let exits = open_steps(ctx.map, ctx.pos);
+                if exits.is_empty() {
+                    return None;
+                }
+
+                Some(AiAction::Move(exits[ctx.rng.range(0, exits.len())]))
+            }
+            Behavior::Wait => Some(AiAction::Wait),
+        }
+    }
+
+    /// Every attack this behavior can use, so the raws can check they exist
+    pub fn attacks(&self) -> Vec<&str> {
+        match self {
+            Behavior::Priority(children) => children.iter().flat_map(|c| c.attacks()).collect(),
+            Behavior::When { then, .. } => then.attacks(),
+            Behavior::Shoot { attack, .. } | Behavior::Shout { attack } => vec![attack.as_str()],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Steps needed to get from one tile to another, counting diagonals as one
+fn tile_distance(a: Point, b: Point) -> i32 {
+    i32::max((a.x - b.x).abs(), (a.y - b.y).abs())
+}
+
+/// Whether a projectile fired from `from` would make it to `to`
+/// Tiles that are blocked without the terrain being to blame stand in for whoever is in the way
+fn has_line_of_fire(map: &Map, from: Point, to: Point) -> bool {
+    let path = crate::trace_projectile(from, to, map, |tile| {
+        let index = map.point2d_to_index(tile);
+        map.blocked_tiles[index] && !map.tiles[index].blocks_movement()
+    });
+
+    path.last() == Some(&to)
+}
+
+/// Where to aim an ability to catch the target from where we stand, if it can right now
+fn aim_ability(ctx: &AiContext, ability: &Ability, target: Point) -> Option<Point> {
+    let offset = target - ctx.pos;
+    match ability.reach {
+        // abilities used in place go off once the target is next to us
+        0 => (tile_distance(ctx.pos, target) == 1).then_some(ctx.pos),
+        1 => (ability.reaches(ctx.pos, target)
+            && ctx.map.is_step_allowed(ctx.pos, offset.x, offset.y))
+        .then_some(target),
+        _ => (ability.reaches(ctx.pos, target) && has_line_of_fire(ctx.map, ctx.pos, target))
+            .then_some(target),
+    }
+}
+
+/// Move towards the target, or hit it with the bump attack once it is next to us
+fn approach(ctx: &AiContext, target: Point) -> Option<AiAction> {
+    let offset = target - ctx.pos;
+    if tile_distance(ctx.pos, target) == 1 && ctx.map.is_step_allowed(ctx.pos, offset.x, offset.y) {
+        return ctx.bump_attack.map(|attack| AiAction::Attack {
+            loc: target,
+            attack: attack.to_string(),
+        });
+    }
+
+    // monsters block their own tile, so there is no path onto them, only up to them
+    let mut around = open_steps(ctx.map, target);
+    around.sort_by_key(|tile| tile_distance(ctx.pos, *tile));
+    step_towards(ctx.map, ctx.pos, target)
+        .or_else(|| {
+            around
+                .into_iter()
+                .find_map(|tile| step_towards(ctx.map, ctx.pos, tile))
+        })
+        .map(AiAction::Move)
+}
+
+/// The first step along the shortest path, if there is one
+fn step_towards(map: &Map, from: Point, to: Point) -> Option<Point> {
+    if !map.in_bounds(to) {
+        return None;
+    }
+
+    let path = rltk::a_star_search(map.point2d_to_index(from), map.point2d_to_index(to), map);
+    if !path.success || path.steps.len() < 2 {
+        return None;
+    }
+
+    Some(map.index_to_point2d(path.steps[1]))
+}
+
+/// The neighbouring tile that gets furthest from `threat`, if any of them are further than now
+fn step_away(map: &Map, from: Point, threat: Point) -> Option<Point> {
+    let distance = |p: Point| rltk::DistanceAlg::PythagorasSquared.distance2d(p, threat);
+    let current = distance(from);
+
+    open_steps(map, from)
+        .into_iter()
+        .map(|step| (step, distance(step)))
+        .filter(|(_, d)| *d > current)
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(step, _)| step)
+}
+
+/// Neighbouring tiles that can be walked onto right now
+fn open_steps(map: &Map, from: Point) -> Vec<Point> {
+    map.get_available_exits(map.point2d_to_index(from))
+        .iter()
+        .map(|(index, _)| map.index_to_point2d(*index))
+        .filter(|step| !map.blocked_tiles[map.point2d_to_index(*step)])
+        .collect()
+}
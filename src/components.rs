@@ -1,8 +1,9 @@
 use rltk::{Point, RGB};
+use serde::{Deserialize, Serialize};
 use specs::prelude::*;
 use specs::Component;
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Position {
     pub x: i32,
     pub y: i32,
@@ -14,40 +15,62 @@ impl Position {
     }
 }
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Renderable {
     pub symbol: rltk::FontCharType,
     pub fg: RGB,
     pub bg: RGB,
 }
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Player;
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct Name {
+    pub name: String,
+}
+
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Viewshed {
     pub visible: Vec<Point>,
     pub dirty: bool,
     pub range: i32,
 }
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct CanActFlag {
     pub is_reaction: bool,
 }
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct CanReactFlag;
 
-#[derive(Component)]
+/// Energy charged to `Schedulable::current` for each kind of action
+/// Attacks carry their own cost in the raws, defaulting to ATTACK_COST
+pub const MOVE_COST: i32 = 24;
+pub const WAIT_COST: i32 = 24;
+pub const ATTACK_COST: i32 = 24;
+pub const REACTION_COST: i32 = 8;
+
+/// An actor gets a turn once `current` counts down to zero, losing `delta` each tick
+/// Whatever it does on that turn is charged back onto `current`
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Schedulable {
     pub current: i32,
-    pub base: i32,
     pub delta: i32,
 }
 
+impl Schedulable {
+    /// Charge the actor for an action, which any slows it is under make dearer
+    pub fn charge(&mut self, cost: i32, statuses: Option<&StatusEffects>) {
+        self.current += statuses.map_or(cost, |statuses| statuses.scale_cost(cost));
+    }
+}
+
 #[derive(Component)]
 pub struct ParticleLifetime {
+    /// Time left before the particle shows up, so projectiles can be drawn moving along their path
+    pub delay: f32,
     pub base: f32,
     pub remaining: f32,
     pub should_fade: bool,
@@ -59,31 +82,194 @@ pub struct CardLifetime {
     pub data: super::CardRequest,
 }
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct BlocksTile;
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Health {
     pub current: i32,
     pub max: i32,
 }
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct DeathTrigger {
-    pub event: crate::EventType,
-    pub range: crate::RangeType,
+    pub attack: String,
 }
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct BumpAttack {
+    pub attack: String,
+}
+
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct AttackIntent {
     pub loc: rltk::Point,
-    pub range: crate::RangeType,
+    pub attack: String,
 }
 
-#[derive(Component)]
+/// An attack that has been declared but will only land once its windup runs out
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct Telegraph {
+    pub attack: String,
+    pub loc: rltk::Point,
+    pub origin: rltk::Point,
+    pub tiles: Vec<rltk::Point>,
+    pub remaining: i32,
+}
+
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct MoveIntent {
     pub loc: rltk::Point,
 }
 
-#[derive(Component)]
-pub struct Moveset {}
+/// An attack that can be aimed at tiles up to `reach` away, then needs time to come back around
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Ability {
+    pub attack: String,
+    /// 0 for abilities used where the user stands, 1 for ones used on a neighbouring tile
+    pub reach: i32,
+    /// Turns of the user's that have to pass before it can be used again
+    #[serde(default)]
+    pub cooldown: i32,
+    /// Turns left until it is ready
+    #[serde(default)]
+    pub remaining: i32,
+}
+
+impl Ability {
+    pub fn is_ready(&self) -> bool {
+        self.remaining <= 0
+    }
+
+    /// Whether `target` is close enough to `origin` to aim at, counting diagonal steps as one
+    pub fn reaches(&self, origin: Point, target: Point) -> bool {
+        let distance = i32::max((target.x - origin.x).abs(), (target.y - origin.y).abs());
+        if self.reach == 0 {
+            distance == 0
+        } else {
+            distance > 0 && distance <= self.reach
+        }
+    }
+}
+
+/// The abilities an entity can pick from, which the player has bound to the number keys
+#[derive(Component, Serialize, Deserialize, Clone, Default)]
+pub struct Moveset {
+    pub abilities: Vec<Ability>,
+}
+
+/// Something lying on the map that can be picked up, with `id` naming its definition in the raws
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct Item {
+    pub id: String,
+}
+
+/// Carried items are kept by their raws ID, and only turn back into entities when dropped
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct Inventory {
+    pub capacity: usize,
+    pub items: Vec<String>,
+}
+
+/// Which side an entity is on, with the raws deciding which factions fight each other
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct Faction {
+    pub name: String,
+}
+
+/// Monsters pick their actions by running their behavior, see behavior.rs
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct Ai {
+    pub behavior: crate::Behavior,
+    /// Where the monster was placed, which patrols and guards are anchored to
+    pub home: Point,
+    pub patrol_step: usize,
+    /// Where the target was when it was last seen or heard about
+    pub last_seen: Option<Point>,
+    /// Turns left before the monster gives up looking for its target
+    pub search_turns: i32,
+    /// How many turns the monster keeps looking after losing track of its target
+    pub patience: i32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone)]
+pub enum ReactionType {
+    Dodge,
+    Parry,
+    Counter,
+    Brace,
+}
+
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct ReactIntent {
+    pub reaction: ReactionType,
+    pub loc: rltk::Point,
+}
+
+#[derive(Component, Serialize, Deserialize, Clone)]
+pub struct Guarding {
+    pub reaction: ReactionType,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone)]
+pub enum StatusType {
+    Poison {
+        damage: i32,
+    },
+    /// Damages like poison, but also catches on whoever stands next to the burning actor
+    Burn {
+        damage: i32,
+    },
+    Stun,
+    /// Everything the actor does costs `amount` percent more
+    Slow {
+        amount: i32,
+    },
+}
+
+impl StatusType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatusType::Poison { .. } => "Poison",
+            StatusType::Burn { .. } => "Burn",
+            StatusType::Stun => "Stun",
+            StatusType::Slow { .. } => "Slow",
+        }
+    }
+
+    pub fn color(&self) -> RGB {
+        match self {
+            StatusType::Poison { .. } => RGB::named(rltk::GREEN),
+            StatusType::Burn { .. } => RGB::named(rltk::ORANGE),
+            StatusType::Stun => RGB::named(rltk::YELLOW),
+            StatusType::Slow { .. } => RGB::named(rltk::LIGHT_BLUE),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StatusEffect {
+    pub status: StatusType,
+    pub duration: i32,
+}
+
+#[derive(Component, Serialize, Deserialize, Clone, Default)]
+pub struct StatusEffects {
+    pub effects: Vec<StatusEffect>,
+}
+
+impl StatusEffects {
+    /// What an action normally costing `cost` costs under these effects
+    pub fn scale_cost(&self, cost: i32) -> i32 {
+        let slow: i32 = self
+            .effects
+            .iter()
+            .map(|effect| match effect.status {
+                StatusType::Slow { amount } => amount,
+                _ => 0,
+            })
+            .sum();
+
+        cost * (100 + slow) / 100
+    }
+}
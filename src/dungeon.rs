@@ -0,0 +1,257 @@
+use super::raws::{self, RawsError};
+use super::rng::GameRng;
+use super::saveload::{self, SavedEntity};
+use super::*;
+use rltk::{Algorithm2D, Point};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A level the player has left, kept so that it can be restored when they come back
+#[derive(Serialize, Deserialize, Clone)]
+struct StoredLevel {
+    map: Map,
+    entities: Vec<SavedEntity>,
+}
+
+/// Every visited level other than the current one, stored as a World resource
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct Dungeon {
+    // ordered, so that the same dungeon always saves to the same JSON
+    levels: BTreeMap<i32, StoredLevel>,
+}
+
+/// Take the stairs the player is standing on, going down for a positive `delta` and up otherwise
+/// Returns false if the player is not on the right kind of stairs
+pub fn change_level(ecs: &mut World, delta: i32) -> Result<bool, RawsError> {
+    let player = *ecs.fetch::<Entity>();
+    let stairs = if delta > 0 {
+        TileType::DownStairs
+    } else {
+        TileType::UpStairs
+    };
+
+    {
+        let map = ecs.fetch::<Map>();
+        let positions = ecs.read_storage::<Position>();
+        let pos = positions
+            .get(player)
+            .expect("Player does not have a Position");
+
+        if map.tiles[map.get_index(pos.x, pos.y)] != stairs {
+            return Ok(false);
+        }
+    }
+
+    let companions = take_companions(ecs, player);
+    let (depth, width, height, move_rules) = store_current_level(ecs, player);
+    let target_depth = depth + delta;
+    let stored = ecs.fetch_mut::<Dungeon>().levels.remove(&target_depth);
+
+    // only a brand new level needs enemies
+    let new_spawns = match stored {
+        Some(level) => {
+            ecs.insert(level.map);
+            for saved in level.entities {
+                saveload::load_entity(ecs, saved);
+            }
+            None
+        }
+        None => {
+            let mut built = {
+                let mut rng = ecs.fetch_mut::<GameRng>();
+                map_builders::build_level(width, height, target_depth, &mut rng)
+            };
+
+            // the run keeps the same movement rules on every level
+            built.map.move_rules = move_rules;
+            ecs.insert(built.map);
+            Some(built.spawns)
+        }
+    };
+
+    place_player(ecs, player, delta);
+    place_companions(ecs, player, companions);
+
+    // anything still pending was aimed at the level we just left
+    ecs.insert(EventStack::default());
+
+    let message = if delta > 0 {
+        format!("You descend to depth {}.", target_depth)
+    } else {
+        format!("You climb up to depth {}.", target_depth)
+    };
+    ecs.fetch_mut::<gamelog::GameLog>().entries.push(message);
+
+    if let Some(spawns) = new_spawns {
+        raws::with_raws(ecs, |raws, ecs| {
+            spawner::populate_level(ecs, raws, &spawns, target_depth)
+        })?;
+    }
+
+    Ok(true)
+}
+
+/// Move the current map and everything on it except the player into the Dungeon
+/// Returns the depth, size and movement rules of the stored map
+fn store_current_level(ecs: &mut World, player: Entity) -> (i32, i32, i32, MoveRules) {
+    let mut stored = Vec::new();
+    {
+        let entities = ecs.entities();
+        let particles = ecs.read_storage::<ParticleLifetime>();
+        let cards = ecs.read_storage::<CardLifetime>();
+
+        for (ent, _, _) in (&entities, !&particles, !&cards).join() {
+            if ent != player {
+                stored.push(ent);
+            }
+        }
+    }
+
+    let saved = stored
+        .iter()
+        .map(|ent| saveload::save_entity(ecs, *ent))
+        .collect();
+
+    ecs.delete_entities(&stored)
+        .expect("Failed to remove entities from the old level");
+
+    let map = ecs.remove::<Map>().expect("There is no current map");
+    let stored_info = (map.depth, map.width, map.height, map.move_rules);
+    ecs.fetch_mut::<Dungeon>().levels.insert(
+        map.depth,
+        StoredLevel {
+            map,
+            entities: saved,
+        },
+    );
+
+    stored_info
+}
+
+/// Pull out the player's allies that can see them, so they can follow the player to the next level
+fn take_companions(ecs: &mut World, player: Entity) -> Vec<SavedEntity> {
+    let mut companions = Vec::new();
+    {
+        let entities = ecs.entities();
+        let positions = ecs.read_storage::<Position>();
+        let viewsheds = ecs.read_storage::<Viewshed>();
+        let factions = ecs.read_storage::<Faction>();
+        let ais = ecs.read_storage::<Ai>();
+        let player_pos = positions
+            .get(player)
+            .expect("Player does not have a Position")
+            .as_point();
+        let player_faction = factions.get(player).map(|faction| &faction.name);
+
+        for (ent, viewshed, faction, _) in (&entities, &viewsheds, &factions, &ais).join() {
+            if Some(&faction.name) == player_faction && viewshed.visible.contains(&player_pos) {
+                companions.push(ent);
+            }
+        }
+    }
+
+    let saved = companions
+        .iter()
+        .map(|ent| saveload::save_entity(ecs, *ent))
+        .collect();
+
+    ecs.delete_entities(&companions)
+        .expect("Failed to remove companions from the old level");
+    saved
+}
+
+/// Bring the companions back in on the free tiles closest to the player
+/// Any that don't fit are left behind for good
+fn place_companions(ecs: &mut World, player: Entity, companions: Vec<SavedEntity>) {
+    let player_pos = ecs
+        .read_storage::<Position>()
+        .get(player)
+        .expect("Player does not have a Position")
+        .as_point();
+    let spots = free_tiles_near(ecs, player_pos, companions.len());
+
+    for (saved, spot) in companions.into_iter().zip(spots) {
+        let ent = saveload::load_entity(ecs, saved);
+
+        if let Some(pos) = ecs.write_storage::<Position>().get_mut(ent) {
+            pos.x = spot.x;
+            pos.y = spot.y;
+        }
+
+        if let Some(viewshed) = ecs.write_storage::<Viewshed>().get_mut(ent) {
+            viewshed.dirty = true;
+        }
+
+        // whatever it was after is back on the old level
+        if let Some(ai) = ecs.write_storage::<Ai>().get_mut(ent) {
+            ai.home = spot;
+            ai.last_seen = None;
+            ai.search_turns = 0;
+        }
+
+        ecs.write_storage::<MoveIntent>().remove(ent);
+        ecs.write_storage::<AttackIntent>().remove(ent);
+        ecs.write_storage::<Telegraph>().remove(ent);
+    }
+}
+
+/// Up to `count` tiles reachable from `center` that can be stood on and have nothing on them,
+/// closest first
+pub fn free_tiles_near(ecs: &World, center: Point, count: usize) -> Vec<Point> {
+    let map = ecs.fetch::<Map>();
+    let occupied: Vec<Point> = (
+        &ecs.read_storage::<Position>(),
+        !&ecs.read_storage::<ParticleLifetime>(),
+    )
+        .join()
+        .map(|(pos, _)| pos.as_point())
+        .collect();
+
+    let mut tiles: Vec<(i32, usize)> = map_builders::flood_fill(&map, center)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, distance)| distance.map(|distance| (distance, index)))
+        .filter(|(_, index)| {
+            !map.tiles[*index].blocks_movement()
+                && !occupied.contains(&map.index_to_point2d(*index))
+        })
+        .collect();
+    tiles.sort_unstable();
+
+    tiles
+        .into_iter()
+        .take(count)
+        .map(|(_, index)| map.index_to_point2d(index))
+        .collect()
+}
+
+/// Put the player on the stairs leading back to the level they came from
+fn place_player(ecs: &mut World, player: Entity, delta: i32) {
+    let arrival = {
+        let map = ecs.fetch::<Map>();
+        let stairs = if delta > 0 {
+            TileType::UpStairs
+        } else {
+            TileType::DownStairs
+        };
+        map.find_tile(stairs)
+            .expect("Every level is built with stairs both ways")
+    };
+
+    let mut positions = ecs.write_storage::<Position>();
+    let mut viewsheds = ecs.write_storage::<Viewshed>();
+    let mut movements = ecs.write_storage::<MoveIntent>();
+    let mut attacks = ecs.write_storage::<AttackIntent>();
+
+    if let Some(pos) = positions.get_mut(player) {
+        pos.x = arrival.x;
+        pos.y = arrival.y;
+    }
+
+    if let Some(viewshed) = viewsheds.get_mut(player) {
+        viewshed.dirty = true;
+    }
+
+    movements.remove(player);
+    attacks.remove(player);
+}
@@ -1,62 +1,505 @@
 use crate::ParticleRequest;
-use rltk::Point;
+use rltk::{Algorithm2D, Point};
+use serde::{Deserialize, Serialize};
 use specs::prelude::*;
 
+#[derive(Serialize, Deserialize, Clone)]
 pub enum EventType {
-    Damage { amount: i32 },
-    ParticleSpawn { request: ParticleRequest },
+    Damage {
+        amount: i32,
+    },
+    /// Restore health, up to each target's maximum
+    Heal {
+        amount: i32,
+    },
+    Push {
+        distance: i32,
+    },
+    ApplyStatus {
+        status: crate::StatusType,
+        duration: i32,
+    },
+    ParticleSpawn {
+        request: ParticleRequest,
+    },
+    Sequence {
+        steps: Vec<SequenceStep>,
+    },
+    /// Pass on where the source last saw its target to the monsters in range
+    Alert,
+    /// Bring a monster from the raws onto every open target tile, on the source's side
+    Summon {
+        monster: String,
+    },
     // ShowCard { request: CardRequest, offset: i32 },
 }
 
+/// One effect of a Sequence, which may only resolve if the effect before it hit something
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SequenceStep {
+    pub event: EventType,
+    #[serde(default)]
+    pub condition: EventCondition,
+}
+
+#[derive(Serialize, Deserialize, Default, PartialEq, Copy, Clone)]
+pub enum EventCondition {
+    #[default]
+    Always,
+    PreviousHit,
+}
+
 pub fn get_name(event: &EventType) -> Option<String> {
     match event {
         EventType::Damage { .. } => Some("Damage".to_string()),
+        EventType::Heal { .. } => Some("Heal".to_string()),
+        EventType::Push { .. } => Some("Push".to_string()),
+        EventType::ApplyStatus { status, .. } => Some(status.name().to_string()),
+        EventType::Sequence { .. } => Some("Sequence".to_string()),
+        EventType::Alert => Some("Alert".to_string()),
+        EventType::Summon { .. } => Some("Summon".to_string()),
         _ => None,
     }
 }
 
-pub fn get_resolver(event: &EventType) -> Box<dyn EventResolver + Send> {
+/// Lines describing each step of an event, shown on its card
+pub fn get_details(event: &EventType) -> Vec<String> {
     match event {
-        EventType::Damage { amount } => Box::new(DamageResolver { amount: *amount }),
+        EventType::Sequence { steps } => steps
+            .iter()
+            .filter_map(|step| {
+                get_name(&step.event).map(|name| match step.condition {
+                    EventCondition::Always => name,
+                    EventCondition::PreviousHit => format!("if hit: {}", name),
+                })
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+impl EventType {
+    /// Every monster this event can summon, so the raws can check they exist
+    pub fn summons(&self) -> Vec<&str> {
+        match self {
+            EventType::Summon { monster } => vec![monster.as_str()],
+            EventType::Sequence { steps } => {
+                steps.iter().flat_map(|step| step.event.summons()).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// The resolver for an event, carrying over whatever of the event it needs besides its type
+pub(super) fn get_resolver(event: &super::Event) -> Box<dyn EventResolver + Send + Sync> {
+    match &event.event_type {
+        EventType::Damage { amount } => Box::new(DamageResolver {
+            amount: *amount,
+            hits_self: event.hits_self,
+        }),
+        EventType::Heal { amount } => Box::new(HealResolver { amount: *amount }),
+        EventType::Push { distance } => Box::new(PushResolver {
+            distance: *distance,
+            hits_self: event.hits_self,
+        }),
+        EventType::ApplyStatus { status, duration } => Box::new(StatusResolver {
+            status: *status,
+            duration: *duration,
+            hits_self: event.hits_self,
+        }),
         EventType::ParticleSpawn { request } => Box::new(ParticleResolver { request: *request }),
+        EventType::Sequence { steps } => Box::new(SequenceResolver {
+            steps: steps.clone(),
+            policy: event.policy,
+            hits_self: event.hits_self,
+        }),
+        EventType::Alert => Box::new(AlertResolver),
+        EventType::Summon { monster } => Box::new(SummonResolver {
+            monster: monster.clone(),
+        }),
     }
 }
 
 pub trait EventResolver {
-    fn resolve(&self, world: &mut World, source: Option<Entity>, targets: Vec<Point>) -> ();
+    /// Apply the event, returning the entities that it had an effect on
+    fn resolve(
+        &self,
+        world: &mut World,
+        source: Option<Entity>,
+        targets: Vec<Point>,
+    ) -> Vec<Entity>;
+}
+
+fn get_display_name(names: &ReadStorage<crate::Name>, ent: Option<Entity>) -> Option<String> {
+    ent.and_then(|ent| names.get(ent)).map(|n| n.name.clone())
 }
 
 pub struct DamageResolver {
     amount: i32,
+    hits_self: bool,
 }
 
 impl EventResolver for DamageResolver {
-    fn resolve(&self, world: &mut World, _source: Option<Entity>, targets: Vec<Point>) {
-        for pos in targets.iter() {
-            super::add_event(
-                &EventType::ParticleSpawn {
-                    request: ParticleRequest {
-                        position: *pos,
-                        color: rltk::RGB::named(rltk::RED),
-                        symbol: rltk::to_cp437('█'),
-                        lifetime: 600.0,
-                    },
-                },
-                &crate::RangeType::Empty,
-                Point::zero(),
-                false,
-            );
+    fn resolve(
+        &self,
+        world: &mut World,
+        source: Option<Entity>,
+        targets: Vec<Point>,
+    ) -> Vec<Entity> {
+        let affected = super::get_affected_entities(world, &targets);
+        let mut damaged = Vec::new();
+        let mut healths = world.write_storage::<crate::Health>();
+        let mut guards = world.write_storage::<crate::Guarding>();
+        let mut log = world.fetch_mut::<crate::gamelog::GameLog>();
+        let names = world.read_storage::<crate::Name>();
+
+        for e_aff in affected.iter() {
+            // attackers are only caught in their own attacks if the attack says so
+            if Some(*e_aff) == source && !self.hits_self {
+                continue;
+            }
+
+            let mut amount = self.amount;
+
+            // parries absorb a single hit entirely, braces soften every hit until the next turn
+            match guards.get(*e_aff).map(|guard| guard.reaction) {
+                Some(crate::ReactionType::Parry) => {
+                    amount = 0;
+                    guards.remove(*e_aff);
+                    log.entries.push("The attack is parried".to_string());
+                }
+                Some(crate::ReactionType::Brace) => {
+                    amount = i32::max(amount - 1, 0);
+                    log.entries.push("The attack is braced against".to_string());
+                }
+                _ => {}
+            }
+
+            let affected = healths.get_mut(*e_aff);
+            if let Some(affected) = affected {
+                let was_alive = affected.current > 0;
+                affected.current -= amount;
+
+                if amount > 0 {
+                    damaged.push(*e_aff);
+                }
+
+                if was_alive && affected.current <= 0 {
+                    let victim = get_display_name(&names, Some(*e_aff));
+                    let killer = get_display_name(&names, source);
+                    log.entries.push(match killer {
+                        Some(killer) => format!("{} killed {}", killer, victim.unwrap_or_default()),
+                        None => format!("{} died", victim.unwrap_or_default()),
+                    });
+                }
+            }
+        }
+
+        // any real hit is enough to bring down a cracked wall
+        if self.amount > 0 {
+            let mut map = world.fetch_mut::<crate::Map>();
+            let mut broke_wall = false;
+
+            for target in targets.iter() {
+                if !map.in_bounds(*target) {
+                    continue;
+                }
+
+                let index = map.point2d_to_index(*target);
+                if map.tiles[index].is_destructible() {
+                    map.tiles[index] = crate::TileType::Rubble;
+                    map.blocked_tiles[index] = false;
+                    broke_wall = true;
+                }
+            }
+
+            if broke_wall {
+                log.entries.push("The wall crumbles".to_string());
+                for viewshed in (&mut world.write_storage::<crate::Viewshed>()).join() {
+                    viewshed.dirty = true;
+                }
+            }
         }
 
+        damaged
+    }
+}
+
+pub struct HealResolver {
+    amount: i32,
+}
+
+impl EventResolver for HealResolver {
+    fn resolve(
+        &self,
+        world: &mut World,
+        _source: Option<Entity>,
+        targets: Vec<Point>,
+    ) -> Vec<Entity> {
         let affected = super::get_affected_entities(world, &targets);
+        let mut healed = Vec::new();
         let mut healths = world.write_storage::<crate::Health>();
 
+        // unlike damage, healing does work on whoever it came from
         for e_aff in affected.iter() {
-            let affected = healths.get_mut(*e_aff);
-            if let Some(mut affected) = affected {
-                affected.current -= self.amount;
+            if let Some(health) = healths.get_mut(*e_aff) {
+                if health.current <= 0 || health.current >= health.max {
+                    continue;
+                }
+
+                health.current = i32::min(health.current + self.amount, health.max);
+                healed.push(*e_aff);
             }
         }
+
+        healed
+    }
+}
+
+pub struct PushResolver {
+    distance: i32,
+    hits_self: bool,
+}
+
+impl EventResolver for PushResolver {
+    fn resolve(
+        &self,
+        world: &mut World,
+        source: Option<Entity>,
+        targets: Vec<Point>,
+    ) -> Vec<Entity> {
+        let affected = super::get_affected_entities(world, &targets);
+        let mut pushed = Vec::new();
+        let mut positions = world.write_storage::<crate::Position>();
+        let mut viewsheds = world.write_storage::<crate::Viewshed>();
+        let mut healths = world.write_storage::<crate::Health>();
+        let blockers = world.read_storage::<crate::BlocksTile>();
+        let names = world.read_storage::<crate::Name>();
+        let mut log = world.fetch_mut::<crate::gamelog::GameLog>();
+        let mut map = world.fetch_mut::<crate::Map>();
+
+        // things are pushed directly away from whoever caused the event
+        let origin = match source.and_then(|ent| positions.get(ent)) {
+            None => return pushed,
+            Some(pos) => pos.as_point(),
+        };
+
+        for e_aff in affected.iter() {
+            if (Some(*e_aff) == source && !self.hits_self) || healths.get(*e_aff).is_none() {
+                continue;
+            }
+
+            let pos = match positions.get_mut(*e_aff) {
+                None => continue,
+                Some(pos) => pos,
+            };
+            let dx = (pos.x - origin.x).signum();
+            let dy = (pos.y - origin.y).signum();
+            let start = pos.as_point();
+            let mut fell = false;
+
+            for _ in 0..self.distance {
+                let (next_x, next_y) = (pos.x + dx, pos.y + dy);
+                if next_x < 0 || next_x >= map.width || next_y < 0 || next_y >= map.height {
+                    break;
+                }
+
+                let next_index = map.get_index(next_x, next_y);
+                if map.tiles[next_index] == crate::TileType::Chasm {
+                    pos.x = next_x;
+                    pos.y = next_y;
+                    fell = true;
+                    break;
+                }
+
+                if map.blocked_tiles[next_index] {
+                    break;
+                }
+
+                pos.x = next_x;
+                pos.y = next_y;
+            }
+
+            // nothing comes back out of a chasm
+            if fell {
+                if let Some(health) = healths.get_mut(*e_aff) {
+                    health.current = 0;
+                }
+
+                let victim = get_display_name(&names, Some(*e_aff));
+                log.entries.push(format!(
+                    "{} falls into the chasm",
+                    victim.unwrap_or_default()
+                ));
+            }
+
+            if pos.as_point() != start {
+                // keep the blocked tiles current so later pushes in this event collide properly
+                if blockers.get(*e_aff).is_some() {
+                    let start_index = map.get_index(start.x, start.y);
+                    let end_index = map.get_index(pos.x, pos.y);
+                    map.blocked_tiles[start_index] = false;
+                    map.blocked_tiles[end_index] = true;
+                }
+
+                if let Some(viewshed) = viewsheds.get_mut(*e_aff) {
+                    viewshed.dirty = true;
+                }
+
+                pushed.push(*e_aff);
+            }
+        }
+
+        pushed
+    }
+}
+
+pub struct AlertResolver;
+
+impl EventResolver for AlertResolver {
+    fn resolve(
+        &self,
+        world: &mut World,
+        source: Option<Entity>,
+        targets: Vec<Point>,
+    ) -> Vec<Entity> {
+        let affected = super::get_affected_entities(world, &targets);
+        let mut alerted = Vec::new();
+        let mut ais = world.write_storage::<crate::Ai>();
+        let factions = world.read_storage::<crate::Faction>();
+        let side = |ent: Entity| factions.get(ent).map(|faction| faction.name.as_str());
+
+        let last_seen = match source
+            .and_then(|ent| ais.get(ent))
+            .and_then(|ai| ai.last_seen)
+        {
+            None => return alerted,
+            Some(last_seen) => last_seen,
+        };
+
+        for e_aff in affected.iter() {
+            // only the source's own side listens
+            if Some(*e_aff) == source || side(*e_aff) != source.and_then(side) {
+                continue;
+            }
+
+            if let Some(ai) = ais.get_mut(*e_aff) {
+                ai.last_seen = Some(last_seen);
+                ai.search_turns = ai.patience;
+                alerted.push(*e_aff);
+            }
+        }
+
+        alerted
+    }
+}
+
+pub struct SummonResolver {
+    monster: String,
+}
+
+impl EventResolver for SummonResolver {
+    fn resolve(
+        &self,
+        world: &mut World,
+        source: Option<Entity>,
+        targets: Vec<Point>,
+    ) -> Vec<Entity> {
+        let mut summoned = Vec::new();
+        let faction =
+            source.and_then(|ent| world.read_storage::<crate::Faction>().get(ent).cloned());
+        let open_tiles: Vec<Point> = {
+            let map = world.fetch::<crate::Map>();
+            targets
+                .into_iter()
+                .filter(|target| {
+                    map.in_bounds(*target) && !map.blocked_tiles[map.point2d_to_index(*target)]
+                })
+                .collect()
+        };
+
+        crate::raws::with_raws(world, |raws, world| {
+            for tile in open_tiles {
+                let ent = match crate::spawner::monster(world, raws, &self.monster, tile.x, tile.y)
+                {
+                    Err(_) => continue,
+                    Ok(ent) => ent,
+                };
+
+                if let Some(faction) = &faction {
+                    world
+                        .write_storage::<crate::Faction>()
+                        .insert(ent, faction.clone())
+                        .expect("Failed to insert Faction");
+                }
+
+                let mut map = world.fetch_mut::<crate::Map>();
+                let index = map.point2d_to_index(tile);
+                map.blocked_tiles[index] = true;
+                summoned.push(ent);
+            }
+        });
+
+        summoned
+    }
+}
+
+pub struct StatusResolver {
+    status: crate::StatusType,
+    duration: i32,
+    hits_self: bool,
+}
+
+impl EventResolver for StatusResolver {
+    fn resolve(
+        &self,
+        world: &mut World,
+        source: Option<Entity>,
+        targets: Vec<Point>,
+    ) -> Vec<Entity> {
+        let affected = super::get_affected_entities(world, &targets);
+        let mut applied = Vec::new();
+        let mut statuses = world.write_storage::<crate::StatusEffects>();
+        let healths = world.read_storage::<crate::Health>();
+        let schedulables = world.read_storage::<crate::Schedulable>();
+
+        for e_aff in affected.iter() {
+            if Some(*e_aff) == source && !self.hits_self {
+                continue;
+            }
+
+            // statuses only tick on turns, so things that never get one can't carry them
+            if healths.get(*e_aff).is_none() || schedulables.get(*e_aff).is_none() {
+                continue;
+            }
+
+            let effects = &mut statuses
+                .entry(*e_aff)
+                .expect("Failed to get StatusEffects entry")
+                .or_insert_with(crate::StatusEffects::default)
+                .effects;
+
+            // reapplying a status refreshes it instead of stacking
+            let kind = std::mem::discriminant(&self.status);
+            match effects
+                .iter_mut()
+                .find(|effect| std::mem::discriminant(&effect.status) == kind)
+            {
+                Some(existing) => {
+                    existing.status = self.status;
+                    existing.duration = i32::max(existing.duration, self.duration);
+                }
+                None => effects.push(crate::StatusEffect {
+                    status: self.status,
+                    duration: self.duration,
+                }),
+            }
+
+            applied.push(*e_aff);
+        }
+
+        applied
     }
 }
 
@@ -65,8 +508,45 @@ pub struct ParticleResolver {
 }
 
 impl EventResolver for ParticleResolver {
-    fn resolve(&self, world: &mut World, _source: Option<Entity>, _targets: Vec<Point>) {
+    fn resolve(
+        &self,
+        world: &mut World,
+        _source: Option<Entity>,
+        _targets: Vec<Point>,
+    ) -> Vec<Entity> {
         let mut builder = world.fetch_mut::<crate::ParticleBuilder>();
         builder.make_particle(self.request);
+        Vec::new()
+    }
+}
+
+pub struct SequenceResolver {
+    steps: Vec<SequenceStep>,
+    policy: super::TargetingPolicy,
+    hits_self: bool,
+}
+
+impl EventResolver for SequenceResolver {
+    fn resolve(
+        &self,
+        world: &mut World,
+        source: Option<Entity>,
+        targets: Vec<Point>,
+    ) -> Vec<Entity> {
+        let mut stack = world.fetch_mut::<super::EventStack>();
+        let targets = std::sync::Arc::new(targets);
+
+        // the first step needs to resolve first, so it goes on the stack last
+        for step in self.steps.iter().rev() {
+            stack.add_step(
+                step,
+                source,
+                std::sync::Arc::clone(&targets),
+                self.policy,
+                self.hits_self,
+            );
+        }
+
+        Vec::new()
     }
 }
@@ -1,61 +1,291 @@
+use super::raws::{AttackDef, ParticleDef};
 use super::CardRequest;
 use rltk::Point;
+use serde::{Deserialize, Serialize};
 use specs::prelude::*;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
 mod event_type;
 mod range_type;
 
-pub use event_type::EventType;
-pub use range_type::RangeType;
+pub use event_type::{EventCondition, EventType, SequenceStep};
+pub use range_type::{
+    clip_to_map, projectile_blockers, resolve_range_at, trace_projectile, RangeType,
+};
 
-lazy_static! {
-    static ref STACK: Mutex<Vec<Event>> = Mutex::new(Vec::new());
-    pub static ref CARDSTACK: Mutex<Vec<CardRequest>> = Mutex::new(Vec::new());
+/// How an event picks its targets when it is revisited after reactions have resolved
+#[derive(Serialize, Deserialize, Default, PartialEq, Copy, Clone)]
+pub enum TargetingPolicy {
+    /// The event lands on the tiles it was aimed at, so moving off of them avoids it
+    #[default]
+    TileLocked,
+    /// The event follows the entities it originally caught, wherever they end up
+    EntityTracking,
 }
 
 struct Event {
-    resolver: Box<dyn event_type::EventResolver + Send>,
+    event_type: EventType,
     name: Option<String>,
     source: Option<Entity>,
+    origin: Point,
     target_tiles: Arc<Vec<Point>>,
+    target_entities: Option<Vec<Entity>>,
+    clip: bool,
+    policy: TargetingPolicy,
     invokes_reaction: bool,
+    hits_self: bool,
+    particle: Option<ParticleDef>,
+    details: Vec<String>,
+    condition: EventCondition,
+    has_card: bool,
 }
 
-pub fn add_event(event_type: &EventType, range: &RangeType, loc: Point, invokes_reaction: bool) {
-    let mut stack = STACK.lock().expect("Failed to lock STACK");
-    let event = Event {
-        resolver: event_type::get_resolver(event_type),
-        name: event_type::get_name(event_type),
-        source: None,
-        target_tiles: Arc::new(range_type::resolve_range_at(range, loc)),
-        invokes_reaction,
-    };
+/// Serializable copy of an Event, with entities replaced by their index in the save
+#[derive(Serialize, Deserialize)]
+struct SavedEvent {
+    event_type: EventType,
+    name: Option<String>,
+    source: Option<usize>,
+    origin: Point,
+    target_tiles: Vec<Point>,
+    target_entities: Option<Vec<usize>>,
+    clip: bool,
+    policy: TargetingPolicy,
+    invokes_reaction: bool,
+    #[serde(default)]
+    hits_self: bool,
+    particle: Option<ParticleDef>,
+    details: Vec<String>,
+    condition: EventCondition,
+    has_card: bool,
+}
 
-    stack.push(event);
+/// Serializable copy of the EventStack, so that a paused stack can be saved and restored
+#[derive(Serialize, Deserialize)]
+pub struct SavedEventStack {
+    events: Vec<SavedEvent>,
+    cards: Vec<CardRequest>,
+    previous_hit: bool,
+}
+
+/// Pending events and the cards shown for them, stored as a World resource
+#[derive(Default)]
+pub struct EventStack {
+    events: Vec<Event>,
+    cards: Vec<CardRequest>,
+    previous_hit: bool,
+}
+
+impl EventStack {
+    pub fn add_event(
+        &mut self,
+        event_type: &EventType,
+        range: &RangeType,
+        loc: Point,
+        source: Option<Entity>,
+        invokes_reaction: bool,
+        policy: TargetingPolicy,
+    ) {
+        let event = Event {
+            event_type: event_type.clone(),
+            name: event_type::get_name(event_type),
+            source,
+            origin: loc,
+            target_tiles: Arc::new(range_type::resolve_range_at(range, loc, loc)),
+            target_entities: None,
+            clip: false,
+            policy,
+            invokes_reaction,
+            hits_self: false,
+            particle: None,
+            details: event_type::get_details(event_type),
+            condition: EventCondition::Always,
+            has_card: false,
+        };
+
+        self.events.push(event);
+    }
+
+    /// Push an attack aimed at `loc` by something standing at `origin`
+    pub fn add_attack(
+        &mut self,
+        attack: &AttackDef,
+        loc: Point,
+        origin: Point,
+        source: Option<Entity>,
+    ) {
+        let event = Event {
+            event_type: attack.event.clone(),
+            name: Some(attack.name.clone()),
+            source,
+            origin,
+            target_tiles: Arc::new(range_type::resolve_range_at(&attack.range, loc, origin)),
+            target_entities: None,
+            clip: attack.clip,
+            policy: attack.policy,
+            invokes_reaction: attack.invokes_reaction,
+            hits_self: attack.hits_self,
+            particle: attack.particle.clone(),
+            details: event_type::get_details(&attack.event),
+            condition: EventCondition::Always,
+            has_card: false,
+        };
+
+        self.events.push(event);
+    }
+
+    /// Push one step of a Sequence onto the stack, targeting the same way as the Sequence did
+    /// Steps are shown on the card of the Sequence, so they do not get their own card or reaction
+    fn add_step(
+        &mut self,
+        step: &SequenceStep,
+        source: Option<Entity>,
+        targets: Arc<Vec<Point>>,
+        policy: TargetingPolicy,
+        hits_self: bool,
+    ) {
+        let event = Event {
+            event_type: step.event.clone(),
+            name: None,
+            source,
+            origin: Point::zero(),
+            target_tiles: targets,
+            target_entities: None,
+            clip: false,
+            policy,
+            invokes_reaction: false,
+            hits_self,
+            particle: None,
+            details: Vec::new(),
+            condition: step.condition,
+            has_card: false,
+        };
+
+        self.events.push(event);
+    }
+
+    pub fn cards(&self) -> &[CardRequest] {
+        &self.cards
+    }
+
+    /// Copy the stack for saving, looking up each entity's index with `index_of`
+    /// Entities without an index (e.g. ones that have since died) are dropped
+    pub fn to_saved(&self, index_of: impl Fn(Entity) -> Option<usize>) -> SavedEventStack {
+        let events = self
+            .events
+            .iter()
+            .map(|event| SavedEvent {
+                event_type: event.event_type.clone(),
+                name: event.name.clone(),
+                source: event.source.and_then(&index_of),
+                origin: event.origin,
+                target_tiles: event.target_tiles.to_vec(),
+                target_entities: event
+                    .target_entities
+                    .as_ref()
+                    .map(|targets| targets.iter().filter_map(|ent| index_of(*ent)).collect()),
+                clip: event.clip,
+                policy: event.policy,
+                invokes_reaction: event.invokes_reaction,
+                hits_self: event.hits_self,
+                particle: event.particle.clone(),
+                details: event.details.clone(),
+                condition: event.condition,
+                has_card: event.has_card,
+            })
+            .collect();
+
+        SavedEventStack {
+            events,
+            cards: self.cards.clone(),
+            previous_hit: self.previous_hit,
+        }
+    }
+
+    /// Rebuild a saved stack, turning indices back into entities with `entity_at`
+    pub fn from_saved(
+        saved: SavedEventStack,
+        entity_at: impl Fn(usize) -> Option<Entity>,
+    ) -> EventStack {
+        let events = saved
+            .events
+            .into_iter()
+            .map(|event| Event {
+                event_type: event.event_type,
+                name: event.name,
+                source: event.source.and_then(&entity_at),
+                origin: event.origin,
+                target_tiles: Arc::new(event.target_tiles),
+                target_entities: event
+                    .target_entities
+                    .map(|targets| targets.into_iter().filter_map(&entity_at).collect()),
+                clip: event.clip,
+                policy: event.policy,
+                invokes_reaction: event.invokes_reaction,
+                hits_self: event.hits_self,
+                particle: event.particle,
+                details: event.details,
+                condition: event.condition,
+                has_card: event.has_card,
+            })
+            .collect();
+
+        EventStack {
+            events,
+            cards: saved.cards,
+            previous_hit: saved.previous_hit,
+        }
+    }
 }
 
 pub fn process_stack(ecs: &mut World) {
     loop {
-        let event = STACK.lock().expect("Failed to lock STACK").pop();
+        let event = ecs.fetch_mut::<EventStack>().events.pop();
         match event {
             None => {
                 break;
             }
-            Some(event) => {
+            Some(mut event) => {
+                if event.condition == EventCondition::PreviousHit
+                    && !ecs.fetch::<EventStack>().previous_hit
+                {
+                    // the step this depends on missed, so skip it
+                    continue;
+                }
+
                 if event.target_tiles.is_empty() {
                     // non-targetted events
                     process_event(ecs, event);
                 } else {
-                    let mut entities_hit = get_affected_entities(ecs, &event.target_tiles);
-
-                    if let Some(card_name) = &event.name {
-                        add_card_to_stack(
-                            ecs,
-                            &entities_hit,
-                            card_name,
-                            Arc::clone(&event.target_tiles),
-                        );
+                    let mut entities_hit;
+
+                    if event.target_entities.is_none() {
+                        // first time seeing this event, so remember who it was aimed at
+                        if event.clip {
+                            let map = ecs.fetch::<crate::Map>();
+                            event.target_tiles = Arc::new(range_type::clip_to_map(
+                                &event.target_tiles,
+                                event.origin,
+                                &map,
+                            ));
+                        }
+
+                        entities_hit = get_affected_entities(ecs, &event.target_tiles);
+                        event.target_entities = Some(entities_hit.clone());
+
+                        if let Some(card_name) = &event.name {
+                            event.has_card = add_card_to_stack(
+                                ecs,
+                                &entities_hit,
+                                card_name,
+                                &event.details,
+                                event.source,
+                                Arc::clone(&event.target_tiles),
+                            );
+                        }
+                    } else {
+                        // the event was paused, so targets may have moved in the meantime
+                        entities_hit = retarget_event(ecs, &mut event);
                     }
 
                     entities_hit.retain(|ent| entity_can_react(ecs, ent));
@@ -71,7 +301,7 @@ pub fn process_stack(ecs: &mut World) {
                         }
 
                         // put the event back on the stack and return control to the main loop
-                        STACK.lock().expect("Failed to lock STACK").push(event);
+                        ecs.fetch_mut::<EventStack>().events.push(event);
                         break;
                     } else {
                         // otherwise resolve the event
@@ -83,12 +313,41 @@ pub fn process_stack(ecs: &mut World) {
     }
 }
 
-fn get_affected_entities(ecs: &mut World, targets: &Vec<Point>) -> Vec<Entity> {
+/// Recompute the targets of a paused event according to its TargetingPolicy
+/// Returns the entities that the event will now hit
+fn retarget_event(ecs: &mut World, event: &mut Event) -> Vec<Entity> {
+    match event.policy {
+        TargetingPolicy::TileLocked => get_affected_entities(ecs, &event.target_tiles),
+        TargetingPolicy::EntityTracking => {
+            let positions = ecs.read_storage::<crate::Position>();
+            let entities = ecs.entities();
+            let mut tracked = Vec::new();
+            let mut tiles = Vec::new();
+
+            for ent in event.target_entities.iter().flatten() {
+                if !entities.is_alive(*ent) {
+                    continue;
+                }
+
+                if let Some(pos) = positions.get(*ent) {
+                    tracked.push(*ent);
+                    tiles.push(pos.as_point());
+                }
+            }
+
+            event.target_tiles = Arc::new(tiles);
+            tracked
+        }
+    }
+}
+
+fn get_affected_entities(ecs: &mut World, targets: &[Point]) -> Vec<Entity> {
     let mut affected = Vec::new();
     let positions = ecs.read_storage::<crate::Position>();
+    let particles = ecs.read_storage::<crate::ParticleLifetime>();
     let entities = ecs.entities();
 
-    for (ent, pos) in (&entities, &positions).join() {
+    for (ent, pos, _) in (&entities, &positions, !&particles).join() {
         for target in targets {
             if pos.as_point() == *target {
                 affected.push(ent);
@@ -106,41 +365,62 @@ fn entity_can_react(ecs: &mut World, target: &Entity) -> bool {
 
 fn add_card_to_stack(
     ecs: &mut World,
-    entities_hit: &Vec<Entity>,
+    entities_hit: &[Entity],
     name: &String,
+    details: &[String],
+    source: Option<Entity>,
     hit_range: Arc<Vec<rltk::Point>>,
-) {
+) -> bool {
     let active_count = current_active_card_count(ecs);
     let player = ecs.fetch::<Entity>();
+    let mut stack = ecs.fetch_mut::<EventStack>();
+    let names = ecs.read_storage::<crate::Name>();
 
     if entities_hit.contains(&*player) {
         let visual_event_data = Some(CardRequest {
             name: name.to_string(),
+            details: details.to_vec(),
+            source: source
+                .and_then(|ent| names.get(ent))
+                .map(|n| n.name.clone()),
             offset: active_count,
             affected: hit_range,
         });
 
         if let Some(visual_event_data) = visual_event_data {
-            CARDSTACK
-                .lock()
-                .expect("Failed to lock CARDSTACK")
-                .push(visual_event_data);
+            stack.cards.push(visual_event_data);
+            return true;
         }
     }
+
+    false
 }
 
 fn process_event(ecs: &mut World, event: Event) {
-    let top_card = CARDSTACK.lock().expect("Failed to lock CARDSTACK").pop();
+    // only events that put up a card take it down again
+    let top_card = if event.has_card {
+        ecs.fetch_mut::<EventStack>().cards.pop()
+    } else {
+        None
+    };
     let active_count = current_active_card_count(ecs);
 
-    if let Some(top_card) = top_card {
+    {
         let mut builder = ecs.fetch_mut::<crate::ParticleBuilder>();
-        builder.make_card(top_card, active_count);
+        if let Some(top_card) = top_card {
+            builder.make_card(top_card, active_count);
+        }
+
+        if let Some(particle) = &event.particle {
+            for pos in event.target_tiles.iter() {
+                builder.make_particle(particle.request_at(*pos));
+            }
+        }
     }
 
-    event
-        .resolver
-        .resolve(ecs, event.source, event.target_tiles.to_vec());
+    let affected =
+        event_type::get_resolver(&event).resolve(ecs, event.source, event.target_tiles.to_vec());
+    ecs.fetch_mut::<EventStack>().previous_hit = !affected.is_empty();
 }
 
 fn current_active_card_count(ecs: &mut World) -> i32 {
@@ -151,3 +431,242 @@ fn current_active_card_count(ecs: &mut World) -> i32 {
     }
     count
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{gamelog::GameLog, spawner, CanActFlag, CanReactFlag, Health, Map, Position};
+
+    /// An open room with a target at (5, 5) that is able to react
+    fn world() -> (World, Entity) {
+        let mut ecs = World::new();
+        spawner::register_components(&mut ecs);
+
+        let mut map = Map::new(12, 12, 0);
+        map.build_room(rltk::Rect::with_size(1, 1, 10, 10));
+        map.set_blocked_tiles();
+        ecs.insert(map);
+        ecs.insert(GameLog {
+            entries: Vec::new(),
+        });
+
+        let target = ecs
+            .create_entity()
+            .with(Position { x: 5, y: 5 })
+            .with(Health { current: 5, max: 5 })
+            .with(crate::Schedulable {
+                current: 100,
+                delta: 4,
+            })
+            .with(CanReactFlag)
+            .build();
+        ecs.insert(target);
+
+        (ecs, target)
+    }
+
+    fn jab(policy: &str) -> AttackDef {
+        let json = format!(
+            r#"{{
+                "id": "jab",
+                "name": "Jab",
+                "event": {{ "Damage": {{ "amount": 1 }} }},
+                "range": "Single",
+                "invokes_reaction": true,
+                "policy": "{}"
+            }}"#,
+            policy
+        );
+        serde_json::from_str(&json).expect("Failed to parse attack")
+    }
+
+    fn blast(hits_self: bool) -> AttackDef {
+        let json = format!(
+            r#"{{
+                "id": "blast",
+                "name": "Blast",
+                "event": {{ "Damage": {{ "amount": 1 }} }},
+                "range": {{ "Square": {{ "size": 1 }} }},
+                "hits_self": {}
+            }}"#,
+            hits_self
+        );
+        serde_json::from_str(&json).expect("Failed to parse attack")
+    }
+
+    /// Have the target set off a blast on the tile next to it
+    fn health_after_own_blast(hits_self: bool) -> i32 {
+        let (mut ecs, target) = world();
+        ecs.fetch_mut::<EventStack>().add_attack(
+            &blast(hits_self),
+            Point::new(6, 5),
+            Point::new(5, 5),
+            Some(target),
+        );
+        process_stack(&mut ecs);
+
+        let healths = ecs.read_storage::<Health>();
+        healths
+            .get(target)
+            .expect("Target does not have Health")
+            .current
+    }
+
+    /// Aim a jab at the target, let it step aside during the reaction, then finish the stack
+    fn health_after_dodge(policy: &str) -> i32 {
+        let (mut ecs, target) = world();
+        ecs.fetch_mut::<EventStack>().add_attack(
+            &jab(policy),
+            Point::new(5, 5),
+            Point::new(4, 5),
+            None,
+        );
+
+        process_stack(&mut ecs);
+        assert!(ecs
+            .read_storage::<CanActFlag>()
+            .get(target)
+            .is_some_and(|flag| flag.is_reaction));
+
+        ecs.write_storage::<Position>()
+            .insert(target, Position { x: 5, y: 6 })
+            .expect("Failed to move target");
+        ecs.write_storage::<CanActFlag>().clear();
+        ecs.write_storage::<CanReactFlag>().remove(target);
+        process_stack(&mut ecs);
+
+        let healths = ecs.read_storage::<Health>();
+        healths
+            .get(target)
+            .expect("Target does not have Health")
+            .current
+    }
+
+    #[test]
+    fn tile_locked_misses_after_dodge() {
+        assert_eq!(health_after_dodge("TileLocked"), 5);
+    }
+
+    #[test]
+    fn entity_tracking_hits_after_dodge() {
+        assert_eq!(health_after_dodge("EntityTracking"), 4);
+    }
+
+    #[test]
+    fn attackers_are_spared_unless_the_attack_hits_self() {
+        assert_eq!(health_after_own_blast(false), 5);
+        assert_eq!(health_after_own_blast(true), 4);
+    }
+
+    /// A single tile attack made of the given Sequence steps, written as JSON
+    fn combo(steps: &str) -> AttackDef {
+        let json = format!(
+            r#"{{
+                "id": "combo",
+                "name": "Combo",
+                "event": {{ "Sequence": {{ "steps": {} }} }},
+                "range": "Single"
+            }}"#,
+            steps
+        );
+        serde_json::from_str(&json).expect("Failed to parse attack")
+    }
+
+    /// Have an attacker standing at (4, 5) use `attack` on the target
+    fn use_on_target(ecs: &mut World, attack: &AttackDef) {
+        let attacker = ecs.create_entity().with(Position { x: 4, y: 5 }).build();
+        ecs.fetch_mut::<EventStack>().add_attack(
+            attack,
+            Point::new(5, 5),
+            Point::new(4, 5),
+            Some(attacker),
+        );
+        process_stack(ecs);
+    }
+
+    fn is_stunned(ecs: &World, ent: Entity) -> bool {
+        ecs.read_storage::<crate::StatusEffects>()
+            .get(ent)
+            .is_some_and(|statuses| {
+                statuses
+                    .effects
+                    .iter()
+                    .any(|effect| effect.status == crate::StatusType::Stun)
+            })
+    }
+
+    const HIT_THEN_STUN: &str = r#"[
+        { "event": { "Damage": { "amount": 1 } } },
+        { "event": { "ApplyStatus": { "status": "Stun", "duration": 4 } }, "condition": "PreviousHit" }
+    ]"#;
+
+    #[test]
+    fn sequence_steps_resolve_in_order() {
+        let (mut ecs, target) = world();
+
+        // pushing first would move the target out from under the damage
+        use_on_target(
+            &mut ecs,
+            &combo(
+                r#"[
+                    { "event": { "Damage": { "amount": 1 } } },
+                    { "event": { "Push": { "distance": 2 } } }
+                ]"#,
+            ),
+        );
+
+        let healths = ecs.read_storage::<Health>();
+        let positions = ecs.read_storage::<Position>();
+        assert_eq!(healths.get(target).map(|health| health.current), Some(4));
+        assert_eq!(
+            positions.get(target).map(|pos| pos.as_point()),
+            Some(Point::new(7, 5))
+        );
+    }
+
+    #[test]
+    fn conditional_step_follows_a_hit() {
+        let (mut ecs, target) = world();
+        use_on_target(&mut ecs, &combo(HIT_THEN_STUN));
+
+        assert!(is_stunned(&ecs, target));
+    }
+
+    #[test]
+    fn conditional_step_is_skipped_after_a_miss() {
+        let (mut ecs, target) = world();
+        ecs.write_storage::<crate::Guarding>()
+            .insert(
+                target,
+                crate::Guarding {
+                    reaction: crate::ReactionType::Parry,
+                },
+            )
+            .expect("Failed to insert Guarding");
+        use_on_target(&mut ecs, &combo(HIT_THEN_STUN));
+
+        let healths = ecs.read_storage::<Health>();
+        assert_eq!(healths.get(target).map(|health| health.current), Some(5));
+        assert!(!is_stunned(&ecs, target));
+    }
+
+    #[test]
+    fn statuses_skip_things_that_never_get_a_turn() {
+        let (mut ecs, target) = world();
+        ecs.write_storage::<crate::Schedulable>().remove(target);
+        use_on_target(&mut ecs, &combo(HIT_THEN_STUN));
+
+        let healths = ecs.read_storage::<Health>();
+        assert_eq!(healths.get(target).map(|health| health.current), Some(4));
+        assert!(!is_stunned(&ecs, target));
+    }
+
+    #[test]
+    fn sequence_is_described_on_one_card() {
+        let attack = combo(HIT_THEN_STUN);
+        assert_eq!(
+            event_type::get_details(&attack.event),
+            vec!["Damage".to_string(), "if hit: Stun".to_string()]
+        );
+    }
+}
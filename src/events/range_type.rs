@@ -1,14 +1,45 @@
-use rltk::Point;
+use crate::{Health, Position};
+use rltk::{Algorithm2D, BaseMap, Point};
+use serde::Deserialize;
+use specs::prelude::*;
 
+#[derive(Deserialize, Clone)]
 pub enum RangeType {
     Empty,
     Single,
-    Square { size: i32 },
-    Custom { offsets: Vec<(i32, i32)> },
+    Square {
+        size: i32,
+    },
+    Diamond {
+        radius: i32,
+    },
+    Ring {
+        radius: i32,
+    },
+    Line {
+        length: i32,
+    },
+    /// A wedge spreading out from the attacker, `angle` degrees wide
+    Cone {
+        length: i32,
+        angle: f32,
+    },
+    /// Offsets are written as if the attacker were facing up
+    Custom {
+        offsets: Vec<(i32, i32)>,
+        #[serde(default)]
+        rotate: bool,
+    },
 }
 
-pub fn resolve_range_at(range: &RangeType, center: Point) -> Vec<Point> {
+/// Resolve a range centered on `center` for an attack made from `origin`
+/// Directional ranges extend away from the origin, so they need the two points to differ
+pub fn resolve_range_at(range: &RangeType, center: Point, origin: Point) -> Vec<Point> {
     let mut targets = Vec::new();
+    let facing = Point::new(
+        (center.x - origin.x).signum(),
+        (center.y - origin.y).signum(),
+    );
 
     match range {
         RangeType::Empty => {}
@@ -22,12 +53,305 @@ pub fn resolve_range_at(range: &RangeType, center: Point) -> Vec<Point> {
                 }
             }
         }
-        RangeType::Custom { offsets } => {
+        RangeType::Diamond { radius } => {
+            for dx in -radius..=*radius {
+                let height = radius - dx.abs();
+                for dy in -height..=height {
+                    targets.push(center + Point::new(dx, dy));
+                }
+            }
+        }
+        RangeType::Ring { radius } => {
+            for dx in -radius..=*radius {
+                for dy in -radius..=*radius {
+                    let dist = rltk::DistanceAlg::Pythagoras
+                        .distance2d(Point::zero(), Point::new(dx, dy))
+                        .round() as i32;
+
+                    if dist == *radius {
+                        targets.push(center + Point::new(dx, dy));
+                    }
+                }
+            }
+        }
+        RangeType::Line { length } => {
+            if facing == Point::zero() {
+                targets.push(center);
+            } else {
+                for step in 0..*length {
+                    targets.push(center + facing * step);
+                }
+            }
+        }
+        RangeType::Cone { length, angle } => {
+            if facing == Point::zero() {
+                targets.push(center);
+            } else {
+                // measure from the tile behind the center so that the center is the tip of the cone
+                let apex = center - facing;
+                let facing_angle = (facing.y as f32).atan2(facing.x as f32);
+                let half_width = angle.to_radians() / 2.0;
+
+                for dx in -length..=*length {
+                    for dy in -length..=*length {
+                        let offset = Point::new(dx, dy);
+                        let dist = rltk::DistanceAlg::Pythagoras.distance2d(Point::zero(), offset);
+                        if offset == Point::zero() || dist > *length as f32 + 0.5 {
+                            continue;
+                        }
+
+                        let mut diff = (dy as f32).atan2(dx as f32) - facing_angle;
+                        while diff > std::f32::consts::PI {
+                            diff -= 2.0 * std::f32::consts::PI;
+                        }
+                        while diff < -std::f32::consts::PI {
+                            diff += 2.0 * std::f32::consts::PI;
+                        }
+
+                        if diff.abs() <= half_width + 0.01 {
+                            targets.push(apex + offset);
+                        }
+                    }
+                }
+            }
+        }
+        RangeType::Custom { offsets, rotate } => {
             for (dx, dy) in offsets {
-                targets.push(center + Point::new(*dx, *dy))
+                let (dx, dy) = if *rotate {
+                    rotate_offset(*dx, *dy, facing)
+                } else {
+                    (*dx, *dy)
+                };
+
+                targets.push(center + Point::new(dx, dy))
             }
         }
     }
 
     targets
 }
+
+/// Rotate an offset written facing up so that it faces the nearest cardinal direction to `facing`
+fn rotate_offset(dx: i32, dy: i32, facing: Point) -> (i32, i32) {
+    if facing.x.abs() > facing.y.abs() {
+        if facing.x > 0 {
+            (-dy, dx)
+        } else {
+            (dy, -dx)
+        }
+    } else if facing.y > 0 {
+        (-dx, -dy)
+    } else {
+        (dx, dy)
+    }
+}
+
+/// Remove targets that are off the map, inside walls, or hidden from the origin by walls
+/// Walls that can be broken stay targetable, but still hide whatever is behind them
+pub fn clip_to_map(targets: &[Point], origin: Point, map: &crate::Map) -> Vec<Point> {
+    targets
+        .iter()
+        .filter(|target| {
+            if !map.in_bounds(**target) {
+                return false;
+            }
+
+            let tile = map.tiles[map.point2d_to_index(**target)];
+            if tile.is_opaque() && !tile.is_destructible() {
+                return false;
+            }
+
+            rltk::line2d(rltk::LineAlg::Bresenham, origin, **target)
+                .iter()
+                .filter(|step| **step != origin && *step != *target)
+                .all(|step| map.in_bounds(*step) && !map.is_opaque(map.point2d_to_index(*step)))
+        })
+        .copied()
+        .collect()
+}
+
+/// The tiles a projectile crosses on its way from `origin` to `target`, ending where it lands
+/// It stops on the first tile `is_occupied` reports, or on the first breakable wall, and just
+/// short of any other wall; an empty path means it never left the origin
+pub fn trace_projectile(
+    origin: Point,
+    target: Point,
+    map: &crate::Map,
+    is_occupied: impl Fn(Point) -> bool,
+) -> Vec<Point> {
+    let mut path = Vec::new();
+
+    for step in rltk::line2d(rltk::LineAlg::Bresenham, origin, target) {
+        if step == origin {
+            continue;
+        }
+
+        if !map.in_bounds(step) {
+            break;
+        }
+
+        let tile = map.tiles[map.point2d_to_index(step)];
+        if tile.is_opaque() && !tile.is_destructible() {
+            break;
+        }
+
+        path.push(step);
+        if tile.is_opaque() || is_occupied(step) {
+            break;
+        }
+    }
+
+    path
+}
+
+/// What stops `shooter`'s projectiles, as the `is_occupied` for trace_projectile
+/// Anything else with health gets in the way, the same for shots, windups and aiming previews
+pub fn projectile_blockers(
+    shooter: Entity,
+    entities: &Entities,
+    positions: &ReadStorage<Position>,
+    healths: &ReadStorage<Health>,
+) -> impl Fn(Point) -> bool {
+    let bodies: Vec<Point> = (entities, positions, healths)
+        .join()
+        .filter(|(ent, _, _)| *ent != shooter)
+        .map(|(_, pos, _)| pos.as_point())
+        .collect();
+
+    move |tile| bodies.contains(&tile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CENTER: Point = Point { x: 5, y: 5 };
+
+    /// Resolve a range aimed at CENTER by an attacker standing one step `from` it
+    fn footprint(range: RangeType, from: (i32, i32)) -> Vec<(i32, i32)> {
+        let origin = CENTER + Point::new(from.0, from.1);
+        let mut tiles: Vec<(i32, i32)> = resolve_range_at(&range, CENTER, origin)
+            .into_iter()
+            .map(|tile| (tile.x - CENTER.x, tile.y - CENTER.y))
+            .collect();
+        tiles.sort();
+        tiles
+    }
+
+    fn sorted(mut tiles: Vec<(i32, i32)>) -> Vec<(i32, i32)> {
+        tiles.sort();
+        tiles
+    }
+
+    const BELOW: (i32, i32) = (0, 1);
+
+    #[test]
+    fn empty_and_single() {
+        assert!(footprint(RangeType::Empty, BELOW).is_empty());
+        assert_eq!(footprint(RangeType::Single, BELOW), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn square() {
+        let mut expected = Vec::new();
+        for x in -1..=1 {
+            for y in -1..=1 {
+                expected.push((x, y));
+            }
+        }
+        assert_eq!(footprint(RangeType::Square { size: 1 }, BELOW), expected);
+    }
+
+    #[test]
+    fn diamond() {
+        assert_eq!(
+            footprint(RangeType::Diamond { radius: 1 }, BELOW),
+            sorted(vec![(0, 0), (-1, 0), (1, 0), (0, -1), (0, 1)])
+        );
+        assert_eq!(footprint(RangeType::Diamond { radius: 2 }, BELOW).len(), 13);
+    }
+
+    #[test]
+    fn ring() {
+        let ring = footprint(RangeType::Ring { radius: 1 }, BELOW);
+        assert_eq!(ring.len(), 8);
+        assert!(!ring.contains(&(0, 0)));
+
+        // rounding keeps the far diagonals out of a radius 2 ring
+        let ring = footprint(RangeType::Ring { radius: 2 }, BELOW);
+        assert!(ring.contains(&(2, 1)));
+        assert!(!ring.contains(&(2, 2)));
+        assert!(!ring.contains(&(1, 1)));
+    }
+
+    #[test]
+    fn line_extends_away_from_the_origin() {
+        let line = |from| footprint(RangeType::Line { length: 3 }, from);
+        assert_eq!(line(BELOW), vec![(0, -2), (0, -1), (0, 0)]);
+        assert_eq!(line((-1, 0)), vec![(0, 0), (1, 0), (2, 0)]);
+        assert_eq!(line((-1, -1)), vec![(0, 0), (1, 1), (2, 2)]);
+        assert_eq!(line((0, 0)), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn cone_spreads_from_its_tip() {
+        let cone = |from| {
+            footprint(
+                RangeType::Cone {
+                    length: 2,
+                    angle: 90.0,
+                },
+                from,
+            )
+        };
+        assert_eq!(
+            cone((-1, 0)),
+            sorted(vec![(0, 0), (1, 0), (0, -1), (0, 1), (1, -1), (1, 1)])
+        );
+        assert_eq!(
+            cone(BELOW),
+            sorted(vec![(0, 0), (0, -1), (-1, 0), (1, 0), (-1, -1), (1, -1)])
+        );
+        assert_eq!(cone((0, 0)), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn custom_offsets_stay_put_without_rotation() {
+        let range = RangeType::Custom {
+            offsets: vec![(0, -1), (1, 0)],
+            rotate: false,
+        };
+        assert_eq!(footprint(range.clone(), BELOW), vec![(0, -1), (1, 0)]);
+        assert_eq!(footprint(range, (-1, 0)), vec![(0, -1), (1, 0)]);
+    }
+
+    #[test]
+    fn custom_offsets_rotate_toward_facing() {
+        // one tile ahead and one to the right, as seen by the attacker
+        let range = RangeType::Custom {
+            offsets: vec![(0, -1), (1, 0)],
+            rotate: true,
+        };
+        let facing_up = footprint(range.clone(), BELOW);
+        let facing_right = footprint(range.clone(), (-1, 0));
+        let facing_down = footprint(range.clone(), (0, -1));
+        let facing_left = footprint(range, (1, 0));
+
+        assert_eq!(facing_up, vec![(0, -1), (1, 0)]);
+        assert_eq!(facing_right, vec![(0, 1), (1, 0)]);
+        assert_eq!(facing_down, vec![(-1, 0), (0, 1)]);
+        assert_eq!(facing_left, vec![(-1, 0), (0, -1)]);
+    }
+
+    #[test]
+    fn rotate_offset_picks_the_nearest_cardinal() {
+        assert_eq!(rotate_offset(1, -2, Point::new(0, -1)), (1, -2));
+        assert_eq!(rotate_offset(1, -2, Point::new(1, 0)), (2, 1));
+        assert_eq!(rotate_offset(1, -2, Point::new(0, 1)), (-1, 2));
+        assert_eq!(rotate_offset(1, -2, Point::new(-1, 0)), (-2, -1));
+
+        // diagonals fall back to the vertical direction
+        assert_eq!(rotate_offset(1, -2, Point::new(1, -1)), (1, -2));
+        assert_eq!(rotate_offset(1, -2, Point::new(-1, 1)), (-1, 2));
+    }
+}
@@ -1,7 +1,14 @@
+use super::player::aimed_ability;
+use super::raws::RawMaster;
+use super::rng::GameRng;
+use super::sys_attack::preview_attack;
+use super::sys_turn::predict_turn_order;
 use super::{
-    CardLifetime, CardRequest, Health, Map, ParticleLifetime, Position, Renderable, TileType,
+    Aim, CanActFlag, CardLifetime, CardRequest, Health, Inventory, Item, Map, Moveset, Name,
+    ParticleLifetime, Position, Renderable, RunState, Schedulable, StatusEffects, Telegraph,
+    TileType,
 };
-use rltk::{Algorithm2D, Rltk, RGB};
+use rltk::{Algorithm2D, Point, Rltk, RGB};
 use specs::prelude::*;
 
 pub fn draw_map(ecs: &World, ctx: &mut Rltk) {
@@ -24,6 +31,38 @@ pub fn draw_map(ecs: &World, ctx: &mut Rltk) {
                     symbol = rltk::to_cp437('#');
                     fg = RGB::from_f32(0., 1.0, 0.);
                 }
+                TileType::DownStairs => {
+                    symbol = rltk::to_cp437('>');
+                    fg = RGB::from_f32(0., 1.0, 1.0);
+                }
+                TileType::UpStairs => {
+                    symbol = rltk::to_cp437('<');
+                    fg = RGB::from_f32(0., 1.0, 1.0);
+                }
+                TileType::DoorClosed => {
+                    symbol = rltk::to_cp437('+');
+                    fg = RGB::from_f32(0.8, 0.5, 0.2);
+                }
+                TileType::DoorOpen => {
+                    symbol = rltk::to_cp437('\'');
+                    fg = RGB::from_f32(0.8, 0.5, 0.2);
+                }
+                TileType::DeepWater => {
+                    symbol = rltk::to_cp437('~');
+                    fg = RGB::from_f32(0.2, 0.4, 1.0);
+                }
+                TileType::Chasm => {
+                    symbol = rltk::to_cp437(':');
+                    fg = RGB::from_f32(0.3, 0.3, 0.5);
+                }
+                TileType::Rubble => {
+                    symbol = rltk::to_cp437(',');
+                    fg = RGB::from_f32(0.6, 0.6, 0.5);
+                }
+                TileType::CrackedWall => {
+                    symbol = rltk::to_cp437('%');
+                    fg = RGB::from_f32(0.5, 0.8, 0.3);
+                }
             }
 
             if !map.visible_tiles[idx] {
@@ -44,10 +83,26 @@ pub fn draw_renderables(ecs: &World, ctx: &mut Rltk) {
     let positions = ecs.read_storage::<Position>();
     let renderables = ecs.read_storage::<Renderable>();
     let particles = ecs.read_storage::<ParticleLifetime>();
+    let items = ecs.read_storage::<Item>();
     let map = ecs.fetch::<Map>();
 
-    for (pos, render, particle) in (&positions, &renderables, (&particles).maybe()).join() {
+    // items go down first, so that anything standing on them is drawn on top
+    let mut drawn: Vec<_> = (
+        &positions,
+        &renderables,
+        (&particles).maybe(),
+        (&items).maybe(),
+    )
+        .join()
+        .collect();
+    drawn.sort_by_key(|(_, _, _, item)| item.is_none());
+
+    for (pos, render, particle, _) in drawn {
         if let Some(lifetime) = particle {
+            if lifetime.delay > 0.0 {
+                continue;
+            }
+
             let mut fg = render.fg;
             let mut bg = render.bg;
 
@@ -68,13 +123,117 @@ pub fn draw_renderables(ecs: &World, ctx: &mut Rltk) {
     }
 }
 
+pub fn draw_telegraphs(ecs: &World, ctx: &mut Rltk) {
+    let telegraphs = ecs.read_storage::<Telegraph>();
+    let map = ecs.fetch::<Map>();
+
+    ctx.set_active_console(0);
+    for telegraph in telegraphs.join() {
+        for pos in telegraph.tiles.iter() {
+            if !map.in_bounds(*pos) || !map.visible_tiles[map.point2d_to_index(*pos)] {
+                continue;
+            }
+
+            ctx.set(
+                pos.x,
+                pos.y,
+                RGB::named(rltk::DARK_ORANGE),
+                RGB::named(rltk::BLACK),
+                rltk::to_cp437('█'),
+            );
+        }
+    }
+    ctx.set_active_console(1);
+}
+
+/// Show where whatever is being aimed would fly and what it would hit if used on `cursor`
+pub fn draw_targeting(ecs: &World, ctx: &mut Rltk, aim: Aim, cursor: Point) {
+    let positions = ecs.read_storage::<Position>();
+    let raws = ecs.fetch::<RawMaster>();
+    let player = ecs.fetch::<Entity>();
+
+    let ability = match aimed_ability(ecs, aim) {
+        None => return,
+        Some(ability) => ability,
+    };
+    let attack_def = match raws.get_attack(&ability.attack) {
+        None => return,
+        Some(attack_def) => attack_def,
+    };
+    let in_reach = positions
+        .get(*player)
+        .is_some_and(|pos| ability.reaches(pos.as_point(), cursor));
+
+    let (path, tiles) = preview_attack(ecs, *player, attack_def, cursor);
+    let map = ecs.fetch::<Map>();
+    let visible =
+        |pos: &Point| map.in_bounds(*pos) && map.visible_tiles[map.point2d_to_index(*pos)];
+
+    ctx.set_active_console(0);
+    for pos in path.iter().filter(|pos| visible(pos)) {
+        ctx.set(
+            pos.x,
+            pos.y,
+            RGB::named(rltk::DIM_GREY),
+            RGB::named(rltk::BLACK),
+            rltk::to_cp437('█'),
+        );
+    }
+
+    for pos in tiles.iter().filter(|pos| visible(pos)) {
+        ctx.set(
+            pos.x,
+            pos.y,
+            RGB::named(rltk::CYAN),
+            RGB::named(rltk::BLACK),
+            rltk::to_cp437('█'),
+        );
+    }
+
+    // tiles out of sight or out of reach can't be aimed at, so the cursor greys out over them
+    let cursor_color = if visible(&cursor) && in_reach {
+        RGB::named(rltk::MAGENTA)
+    } else {
+        RGB::named(rltk::GREY)
+    };
+    ctx.set(
+        cursor.x,
+        cursor.y,
+        cursor_color,
+        RGB::named(rltk::BLACK),
+        rltk::to_cp437('█'),
+    );
+    ctx.set_active_console(1);
+
+    ctx.draw_box(
+        50,
+        27,
+        22,
+        6,
+        RGB::named(rltk::WHITE),
+        RGB::named(rltk::BLACK),
+    );
+    ctx.print_color(
+        51,
+        27,
+        RGB::named(rltk::CYAN),
+        RGB::named(rltk::BLACK),
+        "TARGET",
+    );
+    ctx.print(51, 28, &attack_def.name);
+    ctx.print(51, 30, "dir/mouse Aim");
+    match aim {
+        Aim::Ability(slot) => ctx.print(51, 31, format!("{}/enter   Use", slot + 1)),
+        Aim::Item(_) => ctx.print(51, 31, "enter     Use"),
+    }
+    ctx.print(51, 32, "esc       Cancel");
+}
+
 pub fn draw_cards(ecs: &World, ctx: &mut Rltk) {
     let cards = ecs.read_storage::<CardLifetime>();
-    let card_stack_active = crate::events::CARDSTACK
-        .lock()
-        .expect("Failed to lock CARDSTACK");
+    let stack = ecs.fetch::<crate::EventStack>();
 
-    for (i, card) in card_stack_active.iter().enumerate() {
+    for (i, card) in stack.cards().iter().enumerate() {
         draw_card(card, i as i32, ctx);
 
         ctx.set_active_console(0);
@@ -107,6 +266,20 @@ fn draw_card(card: &CardRequest, offset: i32, ctx: &mut Rltk) {
         RGB::named(rltk::BLACK),
     );
     ctx.print(51 + 3 * offset, 11, card.name.clone());
+
+    for (i, detail) in card.details.iter().enumerate() {
+        ctx.print(51 + 3 * offset, 14 + i as i32, detail);
+    }
+
+    if let Some(source) = &card.source {
+        ctx.print_color(
+            51 + 3 * offset,
+            12,
+            RGB::named(rltk::GREY),
+            RGB::named(rltk::BLACK),
+            source,
+        );
+    }
 }
 
 pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
@@ -118,6 +291,11 @@ pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
         draw_health(player_health, 1, 1, 10, ctx);
     }
 
+    let statuses = ecs.read_storage::<StatusEffects>();
+    if let Some(player_status) = statuses.get(*player) {
+        draw_statuses(player_status, 1, 2, ctx);
+    }
+
     ctx.draw_box(
         0,
         50,
@@ -127,15 +305,222 @@ pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
         RGB::named(rltk::BLACK),
     );
 
+    let depth = format!(" depth {} ", ecs.fetch::<Map>().depth);
+    ctx.print(2, 50, depth);
+
+    // testers need the seed to reproduce a run
+    let seed = format!(" seed {} ", ecs.fetch::<GameRng>().seed());
+    ctx.print(79 - seed.len() - 1, 50, seed);
+
     let log = ecs.fetch::<super::gamelog::GameLog>();
     for (line, message) in log.entries.iter().rev().take(5).enumerate() {
         ctx.print(2, 50 + line + 1, message);
     }
 
     ctx.print(74, 1, format!("{} fps", ctx.fps));
+    draw_timeline(ecs, ctx);
+    draw_abilities(ecs, ctx);
+    draw_reaction_menu(ecs, ctx);
     draw_tooltips(ecs, ctx);
 }
 
+/// Sidebar listing who gets the next few turns, out of the player and the actors they can see
+fn draw_timeline(ecs: &World, ctx: &mut Rltk) {
+    const TIMELINE_LENGTH: usize = 10;
+    const X: i32 = 64;
+    const Y: i32 = 36;
+
+    let entities = ecs.entities();
+    let schedulables = ecs.read_storage::<Schedulable>();
+    let positions = ecs.read_storage::<Position>();
+    let renderables = ecs.read_storage::<Renderable>();
+    let names = ecs.read_storage::<Name>();
+    let map = ecs.fetch::<Map>();
+    let player = ecs.fetch::<Entity>();
+
+    let actors: Vec<Entity> = (&entities, &schedulables, &positions)
+        .join()
+        .filter(|(ent, _, pos)| *ent == *player || map.visible_tiles[map.get_index(pos.x, pos.y)])
+        .map(|(ent, _, _)| ent)
+        .collect();
+    let order = predict_turn_order(ecs, &actors, TIMELINE_LENGTH);
+
+    ctx.draw_box(
+        X,
+        Y,
+        15,
+        TIMELINE_LENGTH as i32 + 1,
+        RGB::named(rltk::WHITE),
+        RGB::named(rltk::BLACK),
+    );
+    ctx.print(X + 1, Y, "NEXT UP");
+
+    for (i, (ent, ticks)) in order.iter().enumerate() {
+        let y = Y + 1 + i as i32;
+        if let Some(render) = renderables.get(*ent) {
+            ctx.set(X + 1, y, render.fg, render.bg, render.symbol);
+        }
+
+        let name = names.get(*ent).map_or("", |name| name.name.as_str());
+        let name: String = name.chars().take(9).collect();
+        ctx.print(X + 3, y, name);
+        ctx.print(X + 13, y, format!("{:>2}", ticks.min(&99)));
+    }
+}
+
+/// The player's moveset, numbered by the key that uses each ability, which only go up to 9
+/// Abilities still cooling down are greyed out, with the turns left until they're ready
+fn draw_abilities(ecs: &World, ctx: &mut Rltk) {
+    const X: i32 = 64;
+    const Y: i32 = 2;
+
+    let movesets = ecs.read_storage::<Moveset>();
+    let raws = ecs.fetch::<RawMaster>();
+    let player = ecs.fetch::<Entity>();
+
+    let abilities = match movesets.get(*player) {
+        Some(moveset) if !moveset.abilities.is_empty() => {
+            &moveset.abilities[..moveset.abilities.len().min(9)]
+        }
+        _ => return,
+    };
+    let targeting = match *ecs.fetch::<RunState>() {
+        RunState::Targeting {
+            aim: Aim::Ability(slot),
+            ..
+        } => Some(slot),
+        _ => None,
+    };
+
+    ctx.draw_box(
+        X,
+        Y,
+        15,
+        abilities.len() as i32 + 1,
+        RGB::named(rltk::WHITE),
+        RGB::named(rltk::BLACK),
+    );
+    ctx.print(X + 1, Y, "ABILITIES");
+
+    for (i, ability) in abilities.iter().enumerate() {
+        let y = Y + 1 + i as i32;
+        let fg = if targeting == Some(i) {
+            RGB::named(rltk::CYAN)
+        } else if ability.is_ready() {
+            RGB::named(rltk::WHITE)
+        } else {
+            RGB::named(rltk::GREY)
+        };
+
+        let name = raws
+            .get_attack(&ability.attack)
+            .map_or(ability.attack.as_str(), |attack| attack.name.as_str());
+        let name: String = name.chars().take(10).collect();
+        ctx.print_color(
+            X + 1,
+            y,
+            fg,
+            RGB::named(rltk::BLACK),
+            format!("{} {}", i + 1, name),
+        );
+
+        if !ability.is_ready() {
+            ctx.print_color(
+                X + 13,
+                y,
+                fg,
+                RGB::named(rltk::BLACK),
+                format!("{:>2}", ability.remaining.min(99)),
+            );
+        }
+    }
+}
+
+/// List what the player is carrying, lettered for picking one to use or drop
+pub fn draw_inventory(ecs: &World, ctx: &mut Rltk) {
+    const X: i32 = 15;
+    const Y: i32 = 10;
+
+    let inventories = ecs.read_storage::<Inventory>();
+    let raws = ecs.fetch::<RawMaster>();
+    let player = ecs.fetch::<Entity>();
+
+    let inventory = match inventories.get(*player) {
+        None => return,
+        Some(inventory) => inventory,
+    };
+
+    ctx.draw_box(
+        X,
+        Y,
+        31,
+        inventory.items.len() as i32 + 4,
+        RGB::named(rltk::WHITE),
+        RGB::named(rltk::BLACK),
+    );
+    ctx.print_color(
+        X + 1,
+        Y,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        format!("INVENTORY {}/{}", inventory.items.len(), inventory.capacity),
+    );
+
+    for (i, id) in inventory.items.iter().enumerate() {
+        let y = Y + 1 + i as i32;
+        let letter = rltk::to_char(b'a' + i as u8);
+        let (glyph, color, name) = match raws.get_item(id) {
+            Ok(item) => (item.glyph, item.rgb, item.name.as_str()),
+            Err(_) => ('?', RGB::named(rltk::WHITE), id.as_str()),
+        };
+
+        ctx.print(X + 1, y, format!("{})", letter));
+        ctx.set(
+            X + 4,
+            y,
+            color,
+            RGB::named(rltk::BLACK),
+            rltk::to_cp437(glyph),
+        );
+        ctx.print(X + 6, y, name);
+    }
+
+    let y = Y + inventory.items.len() as i32 + 2;
+    ctx.print(X + 1, y, "a-z use, shift drop");
+    ctx.print(X + 1, y + 1, "esc close");
+}
+
+fn draw_reaction_menu(ecs: &World, ctx: &mut Rltk) {
+    let can_act = ecs.read_storage::<CanActFlag>();
+    let player = ecs.fetch::<Entity>();
+
+    match can_act.get(*player) {
+        Some(flag) if flag.is_reaction => {}
+        _ => return,
+    }
+
+    ctx.draw_box(
+        50,
+        27,
+        22,
+        7,
+        RGB::named(rltk::WHITE),
+        RGB::named(rltk::BLACK),
+    );
+    ctx.print_color(
+        51,
+        27,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        "REACT",
+    );
+    ctx.print(51, 28, "dir       Dodge");
+    ctx.print(51, 29, "shift+dir Counter");
+    ctx.print(51, 30, "p         Parry");
+    ctx.print(51, 31, "f         Brace");
+    ctx.print(51, 32, "space     Take hit");
+}
+
 fn draw_health(health: &Health, x: i32, y: i32, width: i32, ctx: &mut Rltk) {
     let frac_full = i32::max(width * health.current / health.max, 0);
 
@@ -162,32 +547,47 @@ fn draw_health(health: &Health, x: i32, y: i32, width: i32, ctx: &mut Rltk) {
     ctx.print(x, y, format!("{}/{}", health.current, health.max));
 }
 
+fn draw_statuses(status: &StatusEffects, x: i32, y: i32, ctx: &mut Rltk) {
+    let mut x = x;
+
+    for effect in status.effects.iter() {
+        let label = format!("{} {}", effect.status.name(), effect.duration);
+        ctx.print_color(x, y, effect.status.color(), RGB::named(rltk::BLACK), &label);
+        x += label.len() as i32 + 1;
+    }
+}
+
 fn draw_tooltips(ecs: &World, ctx: &mut Rltk) {
     let map = ecs.fetch::<Map>();
-    let renderables = ecs.read_storage::<Renderable>();
+    let names = ecs.read_storage::<Name>();
     let positions = ecs.read_storage::<Position>();
+    let statuses = ecs.read_storage::<StatusEffects>();
 
     let mouse_point = ctx.mouse_point();
-    if !map.in_bounds(mouse_point) {
+    if !map.in_bounds(mouse_point) || !map.visible_tiles[map.point2d_to_index(mouse_point)] {
         return;
     }
 
-    let mut tooltip: Vec<String> = Vec::new();
+    let mut y = mouse_point.y;
 
-    for (rend, pos) in (&renderables, &positions).join() {
-        if pos.as_point() == mouse_point {
-            tooltip.push(rend.symbol.to_string());
+    for (name, pos, status) in (&names, &positions, (&statuses).maybe()).join() {
+        if pos.as_point() != mouse_point {
+            continue;
         }
-    }
 
-    if !tooltip.is_empty() {
-        // placeholder
         ctx.print_color(
-            1,
-            1,
+            mouse_point.x + 2,
+            y,
             RGB::named(rltk::WHITE),
             RGB::named(rltk::GREY),
-            tooltip.first().unwrap(),
+            &name.name,
         );
+
+        if let Some(status) = status {
+            draw_statuses(status, mouse_point.x + 2, y + 1, ctx);
+            y += 1;
+        }
+
+        y += 1;
     }
 }
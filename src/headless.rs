@@ -0,0 +1,117 @@
+use super::player::{self, Command, Input};
+use super::{sys_particle, RunState, State};
+use specs::prelude::*;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum StopReason {
+    /// The stop condition returned true
+    ConditionMet,
+    /// The player needed to act, but there were no commands left
+    OutOfCommands,
+    /// The step limit was reached first
+    StepLimit,
+}
+
+/// Runs the game systems without a window, taking player input from a list of commands
+pub struct HeadlessRunner {
+    state: State,
+}
+
+impl HeadlessRunner {
+    pub fn new(ecs: World) -> HeadlessRunner {
+        HeadlessRunner {
+            state: State::new(ecs),
+        }
+    }
+
+    /// Pick a run back up at `tick`, such as one loaded from a save
+    pub fn resume(ecs: World, tick: i32) -> HeadlessRunner {
+        let mut state = State::new(ecs);
+        state.tick = tick;
+        HeadlessRunner { state }
+    }
+
+    pub fn world(&self) -> &World {
+        &self.state.ecs
+    }
+
+    pub fn tick(&self) -> i32 {
+        self.state.tick
+    }
+
+    /// Advance by one step: either a pass of the systems, or one command when the player is up
+    /// Commands that can't be performed right now are skipped
+    /// Returns false if the player needs to act but the commands have run out
+    pub fn step<I>(&mut self, commands: &mut I) -> bool
+    where
+        I: Iterator<Item = Command>,
+    {
+        let current_status = *self.state.ecs.fetch::<RunState>();
+        let next_status = match current_status {
+            // scripts give exact commands, so there are no cursors or menus to go through
+            RunState::AwaitingInput | RunState::Targeting { .. } | RunState::Inventory => {
+                match commands.next() {
+                    None => return false,
+                    Some(command) => player::perform_command(&mut self.state.ecs, command),
+                }
+            }
+            RunState::Running => {
+                self.state.run_systems();
+                *self.state.ecs.fetch::<RunState>()
+            }
+        };
+
+        *self.state.ecs.write_resource::<RunState>() = next_status;
+
+        // nothing is drawn, so particles and cards can expire immediately
+        sys_particle::cleanup_particles(&mut self.state.ecs, f32::MAX);
+        self.state.ecs.maintain();
+
+        true
+    }
+
+    /// Feed one frame of input to whichever screen is up, the way the window would
+    /// Input that arrives while the systems are running is ignored, as it would be between frames
+    pub fn input(&mut self, input: &Input) {
+        let ecs = &mut self.state.ecs;
+        let current_status = *ecs.fetch::<RunState>();
+        let next_status = match current_status {
+            RunState::AwaitingInput => player::player_input(ecs, input),
+            RunState::Targeting { aim, cursor } => player::targeting_input(ecs, input, aim, cursor),
+            RunState::Inventory => player::inventory_input(ecs, input),
+            RunState::Running => RunState::Running,
+        };
+
+        *ecs.write_resource::<RunState>() = next_status;
+        ecs.maintain();
+    }
+
+    /// Keep stepping until `done` is satisfied, the commands run out, or `max_steps` is reached
+    pub fn run_until<I, F>(&mut self, commands: I, max_steps: usize, mut done: F) -> StopReason
+    where
+        I: IntoIterator<Item = Command>,
+        F: FnMut(&World) -> bool,
+    {
+        let mut commands = commands.into_iter();
+
+        for _ in 0..max_steps {
+            if done(&self.state.ecs) {
+                return StopReason::ConditionMet;
+            }
+
+            if !self.step(&mut commands) {
+                return StopReason::OutOfCommands;
+            }
+        }
+
+        if done(&self.state.ecs) {
+            StopReason::ConditionMet
+        } else {
+            StopReason::StepLimit
+        }
+    }
+
+    pub fn into_world(self) -> World {
+        self.state.ecs
+    }
+}
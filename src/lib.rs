@@ -0,0 +1,187 @@
+use rltk::{GameState, Rltk, VirtualKeyCode};
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+
+mod behavior;
+mod components;
+pub mod dungeon;
+mod events;
+pub mod gamelog;
+mod gui;
+pub mod headless;
+pub mod map;
+pub mod map_builders;
+pub mod player;
+pub mod raws;
+pub mod rng;
+pub mod saveload;
+pub mod spawner;
+mod sys_ai;
+mod sys_attack;
+mod sys_death;
+mod sys_mapindex;
+mod sys_movement;
+mod sys_particle;
+mod sys_reaction;
+mod sys_telegraph;
+mod sys_turn;
+mod sys_visibility;
+
+pub use behavior::{Behavior, Condition};
+pub use components::*;
+pub use events::*;
+pub use map::{Map, MoveRules, TileType};
+pub use sys_particle::{CardRequest, ParticleBuilder, ParticleRequest};
+
+/// What the player is aiming while targeting
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+pub enum Aim {
+    /// The ability in this slot of the player's moveset
+    Ability(usize),
+    /// The item in this slot of the player's inventory
+    Item(usize),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+pub enum RunState {
+    AwaitingInput,
+    /// Picking where to aim, with `cursor` on the tile being aimed at
+    Targeting {
+        aim: Aim,
+        cursor: rltk::Point,
+    },
+    /// Looking through the player's inventory to pick something to use or drop
+    Inventory,
+    Running,
+}
+
+pub struct State {
+    ecs: World,
+    tick: i32,
+    /// Where the mouse was last frame, so targeting only follows it when it moves
+    /// Updated every frame, so a mouse resting somewhere doesn't count as moving once aiming starts
+    last_mouse: rltk::Point,
+}
+
+impl State {
+    pub fn new(ecs: World) -> State {
+        State {
+            ecs,
+            tick: 0,
+            last_mouse: rltk::Point::zero(),
+        }
+    }
+
+    fn run_systems(&mut self) {
+        self.tick += 1;
+
+        sys_ai::AiSystem.run_now(&self.ecs);
+        sys_turn::TurnSystem.run_now(&self.ecs);
+
+        // reactions are converted into moves and attacks, so they need to run first
+        sys_reaction::ReactionSystem.run_now(&self.ecs);
+
+        sys_movement::MovementSystem.run_now(&self.ecs);
+
+        // windups count down before new ones are declared, so none loses a tick to its own turn
+        sys_telegraph::TelegraphSystem.run_now(&self.ecs);
+        sys_attack::AttackSystem.run_now(&self.ecs);
+
+        // events are processed after everything relevant is added (only attacks currently)
+        events::process_stack(&mut self.ecs);
+
+        // index needs to run after movement so blocked tiles are updated
+        sys_mapindex::MapIndexSystem.run_now(&self.ecs);
+
+        // death needs to run after attacks so bodies are cleaned up
+        sys_death::DeathSystem.run_now(&self.ecs);
+
+        sys_visibility::VisibilitySystem.run_now(&self.ecs);
+        sys_particle::ParticleSpawnSystem.run_now(&self.ecs);
+
+        self.ecs.maintain();
+    }
+}
+
+impl GameState for State {
+    fn tick(&mut self, ctx: &mut Rltk) {
+        // cleanup
+        ctx.set_active_console(0);
+        ctx.cls();
+        ctx.set_active_console(1);
+        ctx.cls();
+        sys_particle::cleanup_particles(&mut self.ecs, ctx.frame_time_ms);
+
+        // saving and loading happen between turns, so the event stack may be paused mid-reaction
+        match ctx.key {
+            Some(VirtualKeyCode::F5) => {
+                let message = match saveload::save_game(self, saveload::SAVE_PATH) {
+                    Ok(()) => "Game saved.".to_string(),
+                    Err(err) => format!("Could not save: {}", err),
+                };
+                self.ecs
+                    .fetch_mut::<gamelog::GameLog>()
+                    .entries
+                    .push(message);
+            }
+            Some(VirtualKeyCode::F9) => {
+                let message = match saveload::load_game(self, saveload::SAVE_PATH) {
+                    Ok(()) => "Game loaded.".to_string(),
+                    Err(err) => format!("Could not load: {}", err),
+                };
+                self.ecs
+                    .fetch_mut::<gamelog::GameLog>()
+                    .entries
+                    .push(message);
+            }
+            _ => {}
+        }
+
+        // draw map + gui
+        gui::draw_map(&self.ecs, ctx);
+        gui::draw_renderables(&self.ecs, ctx);
+        gui::draw_telegraphs(&self.ecs, ctx);
+        gui::draw_cards(&self.ecs, ctx);
+        gui::draw_ui(&self.ecs, ctx);
+
+        let mut next_status;
+        // wrapping to limit borrowed lifetimes
+        {
+            let player = self.ecs.fetch::<Entity>();
+            let can_act = self.ecs.read_storage::<CanActFlag>();
+            match can_act.get(*player) {
+                None => ctx.print(30, 1, format!("OPPONENT TURN {}", self.tick)),
+                Some(_) => ctx.print(30, 1, format!("YOUR TURN {}", self.tick)),
+            }
+
+            // get the current RunState
+            next_status = *self.ecs.fetch::<RunState>();
+        }
+
+        let input = player::Input::read(ctx, &mut self.last_mouse);
+        match next_status {
+            RunState::AwaitingInput => {
+                next_status = player::player_input(&mut self.ecs, &input);
+            }
+            RunState::Targeting { aim, cursor } => {
+                gui::draw_targeting(&self.ecs, ctx, aim, cursor);
+                next_status = player::targeting_input(&mut self.ecs, &input, aim, cursor);
+            }
+            RunState::Inventory => {
+                gui::draw_inventory(&self.ecs, ctx);
+                next_status = player::inventory_input(&mut self.ecs, &input);
+            }
+            RunState::Running => {
+                // uncomment while loop to skip rendering intermediate states
+                while next_status == RunState::Running {
+                    self.run_systems();
+                    // std::thread::sleep(std::time::Duration::from_millis(100));
+                    next_status = *self.ecs.fetch::<RunState>();
+                }
+            }
+        }
+
+        let mut status_writer = self.ecs.write_resource::<RunState>();
+        *status_writer = next_status;
+    }
+}
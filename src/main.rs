@@ -1,121 +1,50 @@
-#[macro_use]
-extern crate lazy_static;
-
-use rltk::{GameState, Rltk, RGB};
-use specs::prelude::*;
-
-mod components;
-mod events;
-mod gamelog;
-mod gui;
-mod map;
-mod player;
-mod sys_ai;
-mod sys_attack;
-mod sys_death;
-mod sys_mapindex;
-mod sys_movement;
-mod sys_particle;
-mod sys_turn;
-mod sys_visibility;
-
-pub use components::*;
-pub use events::*;
-pub use map::{Map, TileType};
-pub use sys_particle::{CardRequest, ParticleBuilder, ParticleRequest};
-
-#[derive(PartialEq, Copy, Clone, Debug)]
-pub enum RunState {
-    AwaitingInput,
-    Running,
-}
-
-pub struct State {
-    ecs: World,
-    tick: i32,
-}
-
-impl State {
-    fn run_systems(&mut self) {
-        self.tick += 1;
-
-        sys_ai::AiSystem.run_now(&self.ecs);
-        sys_turn::TurnSystem.run_now(&self.ecs);
-
-        sys_movement::MovementSystem.run_now(&self.ecs);
-        sys_attack::AttackSystem.run_now(&self.ecs);
-
-        // events are processed after everything relevant is added (only attacks currently)
-        events::process_stack(&mut self.ecs);
+use tymerl::headless::{HeadlessRunner, StopReason};
+use tymerl::rng::GameRng;
+use tymerl::{gamelog, player, raws, spawner, Health, Map, MoveRules, State};
 
-        // index needs to run after movement so blocked tiles are updated
-        sys_mapindex::MapIndexSystem.run_now(&self.ecs);
+const WIDTH: i32 = 80;
+const HEIGHT: i32 = 50;
+const CONSOLE_HEIGHT: i32 = HEIGHT + 7;
+const DEFAULT_HEADLESS_STEPS: usize = 10_000;
 
-        // death needs to run after attacks so bodies are cleaned up
-        sys_death::DeathSystem.run_now(&self.ecs);
+fn main() -> rltk::BError {
+    let args: Vec<String> = std::env::args().collect();
+    let raws = raws::RawMaster::load("raws/raws.json")?;
+
+    // --seed N replays a run, otherwise a random seed is picked
+    let rng = match args.iter().position(|arg| arg == "--seed") {
+        None => GameRng::from_entropy(),
+        Some(index) => GameRng::new(
+            args.get(index + 1)
+                .ok_or("--seed needs a number")?
+                .parse()?,
+        ),
+    };
 
-        sys_visibility::VisibilitySystem.run_now(&self.ecs);
-        sys_particle::ParticleSpawnSystem.run_now(&self.ecs);
+    // --no-diagonals limits everyone to the four cardinal directions
+    let move_rules = MoveRules {
+        diagonals: !args.iter().any(|arg| arg == "--no-diagonals"),
+        ..MoveRules::default()
+    };
 
-        self.ecs.maintain();
+    // --headless <script> [--steps N] runs the script without opening a window
+    if let Some(script_index) = args.iter().position(|arg| arg == "--headless") {
+        let script_path = args
+            .get(script_index + 1)
+            .ok_or("--headless needs a script file")?;
+        let max_steps = match args.iter().position(|arg| arg == "--steps") {
+            None => DEFAULT_HEADLESS_STEPS,
+            Some(index) => args
+                .get(index + 1)
+                .ok_or("--steps needs a number")?
+                .parse()?,
+        };
+
+        return run_headless(raws, rng, move_rules, script_path, max_steps);
     }
-}
 
-impl GameState for State {
-    fn tick(&mut self, ctx: &mut Rltk) {
-        // cleanup
-        ctx.set_active_console(0);
-        ctx.cls();
-        ctx.set_active_console(1);
-        ctx.cls();
-        sys_particle::cleanup_particles(&mut self.ecs, ctx);
-
-        // draw map + gui
-        gui::draw_map(&self.ecs, ctx);
-        gui::draw_renderables(&self.ecs, ctx);
-        gui::draw_cards(&self.ecs, ctx);
-        gui::draw_ui(&self.ecs, ctx);
-
-        let mut next_status;
-        // wrapping to limit borrowed lifetimes
-        {
-            let player = self.ecs.fetch::<Entity>();
-            let can_act = self.ecs.read_storage::<CanActFlag>();
-            match can_act.get(*player) {
-                None => ctx.print(30, 1, format!("OPPONENT TURN {}", self.tick)),
-                Some(_) => ctx.print(30, 1, format!("YOUR TURN {}", self.tick)),
-            }
-
-            // get the current RunState
-            next_status = *self.ecs.fetch::<RunState>();
-        }
-
-        match next_status {
-            RunState::AwaitingInput => {
-                next_status = player::player_input(self, ctx);
-            }
-            RunState::Running => {
-                // uncomment while loop to skip rendering intermediate states
-                while next_status == RunState::Running {
-                    self.run_systems();
-                    // std::thread::sleep(std::time::Duration::from_millis(100));
-                    next_status = *self.ecs.fetch::<RunState>();
-                }
-            }
-        }
-
-        let mut status_writer = self.ecs.write_resource::<RunState>();
-        *status_writer = next_status;
-    }
-}
-
-fn main() -> rltk::BError {
     use rltk::RltkBuilder;
 
-    const WIDTH: i32 = 80;
-    const HEIGHT: i32 = 50;
-    const CONSOLE_HEIGHT: i32 = HEIGHT + 7;
-
     let context = RltkBuilder::simple(WIDTH, CONSOLE_HEIGHT)?
         .with_title("Roguelike Tutorial")
         .with_font("terminal8x8.png", 8, 8)
@@ -123,118 +52,58 @@ fn main() -> rltk::BError {
         .build()
         .expect("Failed to build console");
 
-    let mut gs = State {
-        ecs: World::new(),
-        tick: 0,
-    };
-    gs.ecs.register::<Position>();
-    gs.ecs.register::<Renderable>();
-    gs.ecs.register::<Player>();
-    gs.ecs.register::<Viewshed>();
-    gs.ecs.register::<CanActFlag>();
-    gs.ecs.register::<CanReactFlag>();
-    gs.ecs.register::<Schedulable>();
-    gs.ecs.register::<ParticleLifetime>();
-    gs.ecs.register::<CardLifetime>();
-    gs.ecs.register::<BlocksTile>();
-
-    gs.ecs.register::<Health>();
-    gs.ecs.register::<DeathTrigger>();
-    gs.ecs.register::<AttackIntent>();
-    gs.ecs.register::<MoveIntent>();
-    gs.ecs.register::<Moveset>();
-
-    gs.ecs.insert(RunState::Running);
-    gs.ecs.insert(sys_particle::ParticleBuilder::new());
-
-    let map = map::build_rogue_map(WIDTH, HEIGHT);
-    let player_pos = map.rooms[0].center();
-
-    for room in map.rooms.iter().skip(1) {
-        let (x, y) = room.center().to_tuple();
-        let _enemy = gs
-            .ecs
-            .create_entity()
-            .with(Position { x, y })
-            .with(Renderable {
-                symbol: rltk::to_cp437('x'),
-                fg: RGB::named(rltk::LIGHT_BLUE),
-                bg: RGB::named(rltk::BLACK),
-            })
-            .with(Schedulable {
-                current: 0,
-                base: 24,
-                delta: 4,
-            })
-            .with(Viewshed {
-                visible: Vec::new(),
-                dirty: true,
-                range: 6,
-            })
-            .with(BlocksTile)
-            .with(Health { current: 5, max: 5 })
-            .with(Moveset {})
-            .build();
-    }
+    let gs = State::new(build_world(raws, rng, move_rules)?);
+    rltk::main_loop(context, gs)
+}
 
-    gs.ecs.insert(map);
+/// Build the first level, with movement rules that later levels will carry on with
+fn build_world(
+    raws: raws::RawMaster,
+    rng: GameRng,
+    move_rules: MoveRules,
+) -> Result<specs::World, raws::RawsError> {
+    let ecs = spawner::build_world(raws, rng, WIDTH, HEIGHT)?;
+    ecs.fetch_mut::<Map>().move_rules = move_rules;
+    Ok(ecs)
+}
 
-    let log = gamelog::GameLog {
-        entries: vec!["Hello world!".to_string()],
-    };
-    gs.ecs.insert(log);
-
-    let player = gs
-        .ecs
-        .create_entity()
-        .with(Position {
-            x: player_pos.x,
-            y: player_pos.y,
-        })
-        .with(Renderable {
-            symbol: rltk::to_cp437('@'),
-            fg: RGB::named(rltk::YELLOW),
-            bg: RGB::named(rltk::BLACK),
-        })
-        .with(Player)
-        .with(Schedulable {
-            current: 0,
-            base: 24,
-            delta: 4,
-        })
-        .with(Viewshed {
-            visible: Vec::new(),
-            dirty: true,
-            range: 8,
-        })
-        .with(CanReactFlag)
-        //.with(BlocksTile)
-        .with(Health {
-            current: 10,
-            max: 10,
-        })
-        .build();
-    gs.ecs.insert(player);
-
-    let _explosive_barrel = gs
-        .ecs
-        .create_entity()
-        .with(Position {
-            x: player_pos.x - 1,
-            y: player_pos.y - 1,
-        })
-        .with(Renderable {
-            symbol: rltk::to_cp437('#'),
-            fg: RGB::named(rltk::YELLOW),
-            bg: RGB::named(rltk::BLACK),
-        })
-        .with(BlocksTile)
-        .with(Health { current: 2, max: 2 })
-        .with(DeathTrigger {
-            event: EventType::Damage { amount: 1 },
-            range: RangeType::Square { size: 1 },
-        })
-        .build();
+fn run_headless(
+    raws: raws::RawMaster,
+    rng: GameRng,
+    move_rules: MoveRules,
+    script_path: &str,
+    max_steps: usize,
+) -> rltk::BError {
+    use specs::prelude::*;
+
+    let script = std::fs::read_to_string(script_path)?;
+    let commands = player::parse_script(&script)?;
+
+    let seed = rng.seed();
+    let mut runner = HeadlessRunner::new(build_world(raws, rng, move_rules)?);
+    let reason = runner.run_until(commands, max_steps, |ecs| {
+        let player = ecs.fetch::<Entity>();
+        ecs.read_storage::<Health>()
+            .get(*player)
+            .is_none_or(|health| health.current <= 0)
+    });
+
+    let world = runner.world();
+    for entry in world.fetch::<gamelog::GameLog>().entries.iter() {
+        println!("{}", entry);
+    }
 
-    rltk::main_loop(context, gs)
+    let outcome = match reason {
+        StopReason::ConditionMet => "player died",
+        StopReason::OutOfCommands => "out of commands",
+        StopReason::StepLimit => "step limit reached",
+    };
+    println!(
+        "Stopped after {} ticks with seed {}: {}",
+        runner.tick(),
+        seed,
+        outcome
+    );
+
+    Ok(())
 }
@@ -1,17 +1,87 @@
 use rltk::{Algorithm2D, BaseMap, Point, Rect};
+use serde::{Deserialize, Serialize};
 use std::convert::TryInto;
 
-#[derive(PartialEq, Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone)]
 pub enum TileType {
     Wall,
     Floor,
+    DownStairs,
+    UpStairs,
+    DoorClosed,
+    DoorOpen,
+    DeepWater,
+    Chasm,
+    /// Broken ground that takes longer to cross
+    Rubble,
+    /// A wall weak enough to be broken by attacks, leaving rubble behind
+    CrackedWall,
 }
 
+impl TileType {
+    pub fn is_opaque(self) -> bool {
+        matches!(
+            self,
+            TileType::Wall | TileType::CrackedWall | TileType::DoorClosed
+        )
+    }
+
+    /// Whether walking onto the tile is impossible
+    /// Closed doors block too, but bumping into one opens it
+    pub fn blocks_movement(self) -> bool {
+        matches!(
+            self,
+            TileType::Wall
+                | TileType::CrackedWall
+                | TileType::DoorClosed
+                | TileType::DeepWater
+                | TileType::Chasm
+        )
+    }
+
+    pub fn is_destructible(self) -> bool {
+        self == TileType::CrackedWall
+    }
+
+    /// Extra scheduler time spent by whoever steps onto the tile
+    pub fn move_cost(self) -> i32 {
+        match self {
+            TileType::Rubble => 12,
+            _ => 0,
+        }
+    }
+}
+
+/// How actors are allowed to step between tiles, shared by the player and pathfinding
+#[derive(Serialize, Deserialize, Copy, Clone)]
+pub struct MoveRules {
+    pub diagonals: bool,
+    /// Pathing cost of a diagonal step, where a cardinal step costs 1
+    pub diagonal_cost: f32,
+    /// Whether a diagonal step may squeeze past a single wall
+    /// Stepping between two walls is never allowed
+    pub cut_corners: bool,
+}
+
+impl Default for MoveRules {
+    fn default() -> Self {
+        MoveRules {
+            diagonals: true,
+            diagonal_cost: 1.5,
+            cut_corners: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Map {
     pub tiles: Vec<TileType>,
     pub rooms: Vec<Rect>,
     pub width: i32,
     pub height: i32,
+    /// How many levels below the surface this map is, starting at 0
+    pub depth: i32,
+    pub move_rules: MoveRules,
     pub known_tiles: Vec<bool>,
     pub visible_tiles: Vec<bool>,
     pub blocked_tiles: Vec<bool>,
@@ -19,37 +89,48 @@ pub struct Map {
 
 impl BaseMap for Map {
     fn is_opaque(&self, idx: usize) -> bool {
-        self.tiles[idx] == TileType::Wall
+        self.tiles[idx].is_opaque()
     }
 
     fn get_available_exits(&self, idx: usize) -> rltk::SmallVec<[(usize, f32); 10]> {
         let mut exits = rltk::SmallVec::new();
-        let x = idx as i32 % self.width;
-        let y = idx as i32 / self.width;
-        let w = self.width as usize;
+        let from = self.index_to_point2d(idx);
 
-        // Cardinal directions
-        if self.is_exit_valid(x - 1, y) {
-            exits.push((idx - 1, 1.0))
-        };
-        if self.is_exit_valid(x + 1, y) {
-            exits.push((idx + 1, 1.0))
-        };
-        if self.is_exit_valid(x, y - 1) {
-            exits.push((idx - w, 1.0))
-        };
-        if self.is_exit_valid(x, y + 1) {
-            exits.push((idx + w, 1.0))
-        };
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                if (dx, dy) == (0, 0) || !self.is_step_allowed(from, dx, dy) {
+                    continue;
+                }
+
+                let (x, y) = (from.x + dx, from.y + dy);
+                if self.is_exit_valid(x, y) {
+                    let cost = if dx != 0 && dy != 0 {
+                        self.move_rules.diagonal_cost
+                    } else {
+                        1.0
+                    };
+                    exits.push((self.get_index(x, y), cost));
+                }
+            }
+        }
 
         exits
     }
 
     fn get_pathing_distance(&self, idx1: usize, idx2: usize) -> f32 {
-        let w = self.width as usize;
-        let p1 = Point::new(idx1 % w, idx1 / w);
-        let p2 = Point::new(idx2 % w, idx2 / w);
-        rltk::DistanceAlg::Manhattan.distance2d(p1, p2)
+        let p1 = self.index_to_point2d(idx1);
+        let p2 = self.index_to_point2d(idx2);
+        let dx = (p1.x - p2.x).abs();
+        let dy = (p1.y - p2.y).abs();
+
+        if !self.move_rules.diagonals {
+            return (dx + dy) as f32;
+        }
+
+        // as many diagonal steps as possible, then straight for the rest
+        let diagonal_steps = dx.min(dy);
+        let diagonal_cost = self.move_rules.diagonal_cost.min(2.0);
+        diagonal_steps as f32 * diagonal_cost + (dx.max(dy) - diagonal_steps) as f32
     }
 }
 
@@ -60,14 +141,64 @@ impl Algorithm2D for Map {
 }
 
 impl Map {
+    /// A map of solid wall, for the builders to carve out
+    pub fn new(width: i32, height: i32, depth: i32) -> Map {
+        let dim = (width * height).try_into().unwrap();
+        Map {
+            tiles: vec![TileType::Wall; dim],
+            rooms: vec![],
+            width,
+            height,
+            depth,
+            move_rules: MoveRules::default(),
+            known_tiles: vec![false; dim],
+            visible_tiles: vec![false; dim],
+            blocked_tiles: vec![false; dim],
+        }
+    }
+
     pub fn get_index(&self, x: i32, y: i32) -> usize {
         ((y * self.width) + x) as usize
     }
 
     pub fn set_blocked_tiles(&mut self) {
-        for (index, tile) in self.tiles.iter_mut().enumerate() {
-            let is_blocked = *tile == TileType::Wall;
-            self.blocked_tiles[index] = is_blocked;
+        for (index, tile) in self.tiles.iter().enumerate() {
+            self.blocked_tiles[index] = tile.blocks_movement();
+        }
+    }
+
+    /// Find the first tile of the given type, e.g. to place the player on a staircase
+    pub fn find_tile(&self, tile: TileType) -> Option<Point> {
+        self.tiles
+            .iter()
+            .position(|t| *t == tile)
+            .map(|index| self.index_to_point2d(index))
+    }
+
+    /// Whether a single step from `from` is allowed by the move rules, ignoring what is at
+    /// the destination itself
+    pub fn is_step_allowed(&self, from: Point, dx: i32, dy: i32) -> bool {
+        if dx.abs() > 1 || dy.abs() > 1 || !self.in_bounds(from + Point::new(dx, dy)) {
+            return false;
+        }
+
+        if dx == 0 || dy == 0 {
+            return true;
+        }
+
+        if !self.move_rules.diagonals {
+            return false;
+        }
+
+        // only walls count as corners, anyone standing there can be slipped past
+        let walled = |x: i32, y: i32| self.tiles[self.get_index(x, y)].is_opaque();
+        let side_a = walled(from.x + dx, from.y);
+        let side_b = walled(from.x, from.y + dy);
+
+        if self.move_rules.cut_corners {
+            !(side_a && side_b)
+        } else {
+            !(side_a || side_b)
         }
     }
 
@@ -76,10 +207,12 @@ impl Map {
             return false;
         }
 
-        !self.blocked_tiles[self.get_index(x, y)]
+        // paths may lead through closed doors, since walking into one opens it
+        let index = self.get_index(x, y);
+        !self.blocked_tiles[index] || self.tiles[index] == TileType::DoorClosed
     }
 
-    fn build_room(&mut self, room: Rect) {
+    pub fn build_room(&mut self, room: Rect) {
         for y in room.y1..=room.y2 {
             for x in room.x1..=room.x2 {
                 let index = self.get_index(x, y);
@@ -92,21 +225,18 @@ impl Map {
 
     /// Create a hallway of TileType::Floor between the given start and end points
     /// The hallway will always be built horizontally from the start position and vertically from the end position
-    fn build_hallway(&mut self, start: Point, end: Point) {
-        let xrange;
-        let yrange;
-
-        if start.x > end.x {
-            xrange = (end.x - start.x)..=0;
+    pub fn build_hallway(&mut self, start: Point, end: Point) {
+        let xrange = if start.x > end.x {
+            (end.x - start.x)..=0
         } else {
-            xrange = 0..=(end.x - start.x);
-        }
+            0..=(end.x - start.x)
+        };
 
-        if start.y > end.y {
-            yrange = 0..=(start.y - end.y);
+        let yrange = if start.y > end.y {
+            0..=(start.y - end.y)
         } else {
-            yrange = (start.y - end.y)..=0;
-        }
+            (start.y - end.y)..=0
+        };
 
         for dx in xrange {
             let next_x = start.x + dx;
@@ -125,58 +255,3 @@ impl Map {
         }
     }
 }
-
-pub fn build_rogue_map(width: i32, height: i32) -> Map {
-    let dim = (width * height).try_into().unwrap();
-    let mut map = Map {
-        tiles: vec![TileType::Wall; dim],
-        rooms: vec![],
-        width: width,
-        height: height,
-        known_tiles: vec![false; dim],
-        visible_tiles: vec![false; dim],
-        blocked_tiles: vec![false; dim],
-    };
-
-    let mut rng = rltk::RandomNumberGenerator::new();
-    const MAX_ROOMS: i32 = 30;
-    const MIN_ROOM_WIDTH: i32 = 3;
-    const MAX_ROOM_WIDTH: i32 = 12;
-    const MIN_ROOM_HEIGHT: i32 = 3;
-    const MAX_ROOM_HEIGHT: i32 = 12;
-
-    for _ in 0..MAX_ROOMS {
-        let w = rng.range(MIN_ROOM_WIDTH, MAX_ROOM_WIDTH);
-        let h = rng.range(MIN_ROOM_HEIGHT, MAX_ROOM_HEIGHT);
-        let x = rng.range(1, map.width - w - 1);
-        let y = rng.range(1, map.height - h - 1);
-
-        let new_room = Rect::with_size(x, y, w, h);
-        let mut quit = false;
-
-        for other_rooms in map.rooms.iter() {
-            if other_rooms.intersect(&new_room) {
-                quit = true;
-            }
-        }
-
-        if quit {
-            continue;
-        }
-
-        map.build_room(new_room);
-        if map.rooms.len() > 1 {
-            let new_center = new_room.center();
-            let prev_center = map.rooms[map.rooms.len() - 2].center();
-
-            if rng.rand::<f32>() > 0.5 {
-                map.build_hallway(prev_center, new_center);
-            } else {
-                map.build_hallway(new_center, prev_center);
-            }
-        }
-    }
-
-    map.set_blocked_tiles();
-    map
-}
@@ -0,0 +1,97 @@
+use super::{finalize, BuiltMap, MapBuilder};
+use crate::{rng::GameRng, Map};
+use rltk::Rect;
+
+/// Rooms from binary space partitioning: the map is split in half until the pieces are small,
+/// then a room is placed in each piece and joined to the room before it
+pub struct BspBuilder {
+    /// Pieces are not split any further once either side would be smaller than this
+    pub min_leaf_size: i32,
+    /// Tiles kept clear between a room and the edge of its piece
+    pub room_padding: i32,
+    pub min_room_size: i32,
+}
+
+impl Default for BspBuilder {
+    fn default() -> Self {
+        BspBuilder {
+            min_leaf_size: 8,
+            room_padding: 1,
+            min_room_size: 3,
+        }
+    }
+}
+
+impl BspBuilder {
+    fn split(&self, area: Rect, rng: &mut GameRng, leaves: &mut Vec<Rect>) {
+        let w = area.x2 - area.x1;
+        let h = area.y2 - area.y1;
+        let can_split_x = w >= self.min_leaf_size * 2;
+        let can_split_y = h >= self.min_leaf_size * 2;
+
+        // prefer splitting across the longer side, so the pieces stay roughly square
+        let split_x = match (can_split_x, can_split_y) {
+            (false, false) => {
+                leaves.push(area);
+                return;
+            }
+            (true, false) => true,
+            (false, true) => false,
+            (true, true) => w > h || (w == h && rng.range(0, 2) == 0),
+        };
+
+        if split_x {
+            let at = rng.range(
+                area.x1 + self.min_leaf_size,
+                area.x2 - self.min_leaf_size + 1,
+            );
+            self.split(Rect::with_exact(area.x1, area.y1, at, area.y2), rng, leaves);
+            self.split(Rect::with_exact(at, area.y1, area.x2, area.y2), rng, leaves);
+        } else {
+            let at = rng.range(
+                area.y1 + self.min_leaf_size,
+                area.y2 - self.min_leaf_size + 1,
+            );
+            self.split(Rect::with_exact(area.x1, area.y1, area.x2, at), rng, leaves);
+            self.split(Rect::with_exact(area.x1, at, area.x2, area.y2), rng, leaves);
+        }
+    }
+}
+
+impl MapBuilder for BspBuilder {
+    fn build(&self, width: i32, height: i32, depth: i32, rng: &mut GameRng) -> BuiltMap {
+        let mut map = Map::new(width, height, depth);
+
+        let mut leaves = Vec::new();
+        self.split(
+            Rect::with_exact(1, 1, width - 2, height - 2),
+            rng,
+            &mut leaves,
+        );
+
+        for leaf in leaves {
+            let max_w = leaf.x2 - leaf.x1 - self.room_padding * 2;
+            let max_h = leaf.y2 - leaf.y1 - self.room_padding * 2;
+            if max_w < self.min_room_size || max_h < self.min_room_size {
+                continue;
+            }
+
+            let w = rng.range(self.min_room_size, max_w + 1);
+            let h = rng.range(self.min_room_size, max_h + 1);
+            let x = leaf.x1 + self.room_padding + rng.range(0, max_w - w + 1);
+            let y = leaf.y1 + self.room_padding + rng.range(0, max_h - h + 1);
+
+            // leaves come out in split order, so neighbouring rooms are usually close together
+            let room = Rect::with_exact(x, y, x + w - 1, y + h - 1);
+            map.build_room(room);
+            if map.rooms.len() > 1 {
+                let prev_center = map.rooms[map.rooms.len() - 2].center();
+                map.build_hallway(prev_center, room.center());
+            }
+        }
+
+        let start = map.rooms[0].center();
+        let spawns = map.rooms.iter().skip(1).map(|room| room.center()).collect();
+        finalize(map, start, spawns)
+    }
+}
@@ -0,0 +1,69 @@
+use super::{central_floor_tile, finalize, random_floor_tiles, BuiltMap, MapBuilder};
+use crate::{rng::GameRng, Map, TileType};
+
+/// Caves grown by cellular automata: random noise smoothed out by repeatedly
+/// turning each tile into whatever most of its neighbours are
+pub struct CellularBuilder {
+    /// Chance for each tile to start out as wall
+    pub wall_chance: f32,
+    pub iterations: i32,
+    /// A tile becomes wall when more than this many of its eight neighbours are walls
+    pub wall_threshold: i32,
+    pub spawn_count: i32,
+}
+
+impl Default for CellularBuilder {
+    fn default() -> Self {
+        CellularBuilder {
+            wall_chance: 0.55,
+            iterations: 15,
+            wall_threshold: 4,
+            spawn_count: 12,
+        }
+    }
+}
+
+impl MapBuilder for CellularBuilder {
+    fn build(&self, width: i32, height: i32, depth: i32, rng: &mut GameRng) -> BuiltMap {
+        let mut map = Map::new(width, height, depth);
+
+        for y in 1..height - 1 {
+            for x in 1..width - 1 {
+                if rng.rand::<f32>() > self.wall_chance {
+                    let index = map.get_index(x, y);
+                    map.tiles[index] = TileType::Floor;
+                }
+            }
+        }
+
+        for _ in 0..self.iterations {
+            let mut next = map.tiles.clone();
+
+            for y in 1..height - 1 {
+                for x in 1..width - 1 {
+                    let mut walls = 0;
+                    for dy in -1..=1 {
+                        for dx in -1..=1 {
+                            let neighbour = map.get_index(x + dx, y + dy);
+                            if (dx, dy) != (0, 0) && map.tiles[neighbour] == TileType::Wall {
+                                walls += 1;
+                            }
+                        }
+                    }
+
+                    next[map.get_index(x, y)] = if walls > self.wall_threshold || walls == 0 {
+                        TileType::Wall
+                    } else {
+                        TileType::Floor
+                    };
+                }
+            }
+
+            map.tiles = next;
+        }
+
+        let start = central_floor_tile(&mut map);
+        let spawns = random_floor_tiles(&map, self.spawn_count, rng);
+        finalize(map, start, spawns)
+    }
+}
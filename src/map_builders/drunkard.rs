@@ -0,0 +1,67 @@
+use super::{finalize, random_floor_tiles, BuiltMap, MapBuilder};
+use crate::{rng::GameRng, Map, TileType};
+use rltk::Point;
+
+/// Winding tunnels dug by diggers stumbling around at random
+/// Every digger after the first starts somewhere already dug, so the tunnels always join up
+pub struct DrunkardBuilder {
+    /// Digging stops once this fraction of the map is floor
+    pub floor_fraction: f32,
+    /// Steps each digger takes before a new one starts
+    pub lifetime: i32,
+    pub spawn_count: i32,
+}
+
+impl Default for DrunkardBuilder {
+    fn default() -> Self {
+        DrunkardBuilder {
+            floor_fraction: 0.4,
+            lifetime: 400,
+            spawn_count: 12,
+        }
+    }
+}
+
+impl MapBuilder for DrunkardBuilder {
+    fn build(&self, width: i32, height: i32, depth: i32, rng: &mut GameRng) -> BuiltMap {
+        let mut map = Map::new(width, height, depth);
+        let start = Point::new(width / 2, height / 2);
+        let start_index = map.get_index(start.x, start.y);
+        map.tiles[start_index] = TileType::Floor;
+
+        // diggers can only reach the tiles inside the border, so never ask for more than that
+        let diggable = ((width - 4) * (height - 4)) as usize;
+        let target = (((width * height) as f32 * self.floor_fraction) as usize).min(diggable);
+        let mut floor_count = 1;
+        let mut dug = vec![start];
+
+        while floor_count < target {
+            let mut digger = dug[rng.range(0, dug.len())];
+
+            for _ in 0..self.lifetime {
+                let index = map.get_index(digger.x, digger.y);
+                if map.tiles[index] == TileType::Wall {
+                    map.tiles[index] = TileType::Floor;
+                    floor_count += 1;
+                    dug.push(digger);
+                }
+
+                let (dx, dy) = match rng.range(0, 4) {
+                    0 => (-1, 0),
+                    1 => (1, 0),
+                    2 => (0, -1),
+                    _ => (0, 1),
+                };
+
+                // stay off the outer edge so the map keeps its wall border
+                let next = Point::new(digger.x + dx, digger.y + dy);
+                if next.x > 1 && next.x < width - 2 && next.y > 1 && next.y < height - 2 {
+                    digger = next;
+                }
+            }
+        }
+
+        let spawns = random_floor_tiles(&map, self.spawn_count, rng);
+        finalize(map, start, spawns)
+    }
+}
@@ -0,0 +1,157 @@
+use super::{flood_fill, random_floor_tiles, BuiltMap, MapBuilder};
+use crate::{rng::GameRng, Map, TileType};
+use rltk::{Algorithm2D, Point};
+
+/// Dresses up another builder's map with doors, rubble, cracked walls, and pools of water
+/// or chasm, without ever cutting off anything the start could reach before
+pub struct FeatureBuilder {
+    pub base: Box<dyn MapBuilder>,
+    /// Chance for each doorway into a room to get a door
+    pub door_chance: f32,
+    pub rubble_chance: f32,
+    /// Chance for each wall next to the floor to be cracked
+    pub cracked_wall_chance: f32,
+    pub pool_count: i32,
+    pub max_pool_size: i32,
+}
+
+impl FeatureBuilder {
+    pub fn new(base: Box<dyn MapBuilder>) -> FeatureBuilder {
+        FeatureBuilder {
+            base,
+            door_chance: 0.6,
+            rubble_chance: 0.03,
+            cracked_wall_chance: 0.04,
+            pool_count: 3,
+            max_pool_size: 8,
+        }
+    }
+
+    fn add_doors(&self, map: &mut Map, rng: &mut GameRng) {
+        for y in 1..map.height - 1 {
+            for x in 1..map.width - 1 {
+                if map.tiles[map.get_index(x, y)] != TileType::Floor || in_room(map, x, y) {
+                    continue;
+                }
+
+                // a doorway is a gap in a wall, with a room on one side of it
+                let is_wall =
+                    |dx: i32, dy: i32| map.tiles[map.get_index(x + dx, y + dy)] == TileType::Wall;
+                let opens_on_room =
+                    |dx: i32, dy: i32| in_room(map, x + dx, y + dy) || in_room(map, x - dx, y - dy);
+                let is_doorway = (is_wall(-1, 0) && is_wall(1, 0) && opens_on_room(0, 1))
+                    || (is_wall(0, -1) && is_wall(0, 1) && opens_on_room(1, 0));
+
+                if is_doorway && rng.rand::<f32>() < self.door_chance {
+                    let index = map.get_index(x, y);
+                    map.tiles[index] = TileType::DoorClosed;
+                }
+            }
+        }
+    }
+
+    fn add_scattered(&self, map: &mut Map, start: Point, rng: &mut GameRng) {
+        for y in 1..map.height - 1 {
+            for x in 1..map.width - 1 {
+                let index = map.get_index(x, y);
+                match map.tiles[index] {
+                    TileType::Floor
+                        if Point::new(x, y) != start && rng.rand::<f32>() < self.rubble_chance =>
+                    {
+                        map.tiles[index] = TileType::Rubble;
+                    }
+                    TileType::Wall => {
+                        let next_to_floor =
+                            [(-1, 0), (1, 0), (0, -1), (0, 1)].iter().any(|(dx, dy)| {
+                                map.tiles[map.get_index(x + dx, y + dy)] == TileType::Floor
+                            });
+
+                        if next_to_floor && rng.rand::<f32>() < self.cracked_wall_chance {
+                            map.tiles[index] = TileType::CrackedWall;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Grow a blob of water or chasm from a random floor tile
+    /// The pool is thrown away again if it would cut off any reachable tile
+    fn add_pool(&self, map: &mut Map, start: Point, spawns: &[Point], rng: &mut GameRng) {
+        let pool_tile = if rng.range(0, 2) == 0 {
+            TileType::DeepWater
+        } else {
+            TileType::Chasm
+        };
+
+        let can_flood = |map: &Map, p: Point| {
+            map.in_bounds(p)
+                && map.tiles[map.point2d_to_index(p)] == TileType::Floor
+                && p != start
+                && !spawns.contains(&p)
+        };
+
+        let mut pos = match random_floor_tiles(map, 1, rng).first() {
+            Some(pos) if can_flood(map, *pos) => *pos,
+            _ => return,
+        };
+
+        let before = flood_fill(map, start);
+        let old_tiles = map.tiles.clone();
+
+        for _ in 0..rng.range(1, self.max_pool_size + 1) {
+            if can_flood(map, pos) {
+                let index = map.point2d_to_index(pos);
+                map.tiles[index] = pool_tile;
+            }
+
+            let (dx, dy) = match rng.range(0, 4) {
+                0 => (-1, 0),
+                1 => (1, 0),
+                2 => (0, -1),
+                _ => (0, 1),
+            };
+            pos = Point::new(pos.x + dx, pos.y + dy);
+        }
+
+        map.set_blocked_tiles();
+        let after = flood_fill(map, start);
+        let cuts_off = before
+            .iter()
+            .zip(after.iter())
+            .enumerate()
+            .any(|(index, (was, now))| {
+                was.is_some() && now.is_none() && map.tiles[index] != pool_tile
+            });
+
+        if cuts_off {
+            map.tiles = old_tiles;
+            map.set_blocked_tiles();
+        }
+    }
+}
+
+/// Rooms are built with inclusive corners, unlike Rect::point_in_rect expects
+fn in_room(map: &Map, x: i32, y: i32) -> bool {
+    map.rooms
+        .iter()
+        .any(|room| x >= room.x1 && x <= room.x2 && y >= room.y1 && y <= room.y2)
+}
+
+impl MapBuilder for FeatureBuilder {
+    fn build(&self, width: i32, height: i32, depth: i32, rng: &mut GameRng) -> BuiltMap {
+        let mut built = self.base.build(width, height, depth, rng);
+        let start = built.start;
+
+        self.add_doors(&mut built.map, rng);
+        self.add_scattered(&mut built.map, start, rng);
+        built.map.set_blocked_tiles();
+
+        for _ in 0..self.pool_count {
+            self.add_pool(&mut built.map, start, &built.spawns, rng);
+        }
+
+        built
+    }
+}
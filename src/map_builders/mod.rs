@@ -0,0 +1,263 @@
+use super::{rng::GameRng, Map, TileType};
+use rltk::{Algorithm2D, BaseMap, Point};
+use std::collections::VecDeque;
+
+mod bsp;
+mod cellular;
+mod drunkard;
+mod features;
+mod prefab;
+mod rooms;
+
+pub use bsp::BspBuilder;
+pub use cellular::CellularBuilder;
+pub use drunkard::DrunkardBuilder;
+pub use features::FeatureBuilder;
+pub use prefab::{Prefab, PrefabBuilder};
+pub use rooms::RoomsBuilder;
+
+/// A finished level: the map, where the player starts, and where enemies can be placed
+pub struct BuiltMap {
+    pub map: Map,
+    pub start: Point,
+    pub spawns: Vec<Point>,
+}
+
+pub trait MapBuilder {
+    fn build(&self, width: i32, height: i32, depth: i32, rng: &mut GameRng) -> BuiltMap;
+}
+
+/// Pick a generator for a level
+/// The first level is always rooms and corridors, deeper ones can be anything
+pub fn builder_for_depth(depth: i32, rng: &mut GameRng) -> Box<dyn MapBuilder> {
+    let base: Box<dyn MapBuilder> = if depth == 0 {
+        Box::new(RoomsBuilder::default())
+    } else {
+        match rng.range(0, 5) {
+            0 => Box::new(RoomsBuilder::default()),
+            1 => Box::new(BspBuilder::default()),
+            2 => Box::new(CellularBuilder::default()),
+            3 => Box::new(DrunkardBuilder::default()),
+            _ => Box::new(PrefabBuilder::new(Box::new(BspBuilder::default()))),
+        }
+    };
+
+    Box::new(FeatureBuilder::new(base))
+}
+
+/// Generate a level with the builder for its depth
+/// finalize walls off whatever the start can't reach, so every level comes out connected
+pub fn build_level(width: i32, height: i32, depth: i32, rng: &mut GameRng) -> BuiltMap {
+    builder_for_depth(depth, rng).build(width, height, depth, rng)
+}
+
+/// Shared last step for every builder: remove anything the start can't reach, then add stairs
+/// Down stairs go on the reachable tile furthest from the start, up stairs on the start itself
+fn finalize(mut map: Map, start: Point, spawns: Vec<Point>) -> BuiltMap {
+    let distances = cull_unreachable(&mut map, start);
+    let spawns = reachable_spawns(&map, &distances, start, spawns);
+
+    let start_index = map.point2d_to_index(start);
+    let furthest = distances
+        .iter()
+        .enumerate()
+        .filter_map(|(index, distance)| distance.map(|d| (index, d)))
+        .max_by_key(|(index, distance)| (*distance, std::cmp::Reverse(*index)))
+        .map(|(index, _)| index)
+        .unwrap_or(start_index);
+
+    map.tiles[furthest] = TileType::DownStairs;
+    if map.depth > 0 {
+        map.tiles[start_index] = TileType::UpStairs;
+    }
+
+    map.set_blocked_tiles();
+
+    BuiltMap { map, start, spawns }
+}
+
+/// Wall off every tile that can't be reached from `start`, returning the distances to the rest
+fn cull_unreachable(map: &mut Map, start: Point) -> Vec<Option<i32>> {
+    map.set_blocked_tiles();
+    let distances = flood_fill(map, start);
+
+    for (index, distance) in distances.iter().enumerate() {
+        if distance.is_none() {
+            map.tiles[index] = TileType::Wall;
+        }
+    }
+
+    map.set_blocked_tiles();
+    distances
+}
+
+fn reachable_spawns(
+    map: &Map,
+    distances: &[Option<i32>],
+    start: Point,
+    spawns: Vec<Point>,
+) -> Vec<Point> {
+    spawns
+        .into_iter()
+        .filter(|spawn| *spawn != start && map.in_bounds(*spawn))
+        .filter(|spawn| distances[map.point2d_to_index(*spawn)].is_some())
+        .collect()
+}
+
+/// Steps from `start` to every tile, following get_available_exits
+/// Tiles that can't be reached are None
+pub fn flood_fill(map: &Map, start: Point) -> Vec<Option<i32>> {
+    let mut distances = vec![None; map.tiles.len()];
+    let start_index = map.point2d_to_index(start);
+    let mut frontier = VecDeque::new();
+
+    distances[start_index] = Some(0);
+    frontier.push_back(start_index);
+
+    while let Some(index) = frontier.pop_front() {
+        let next_distance = distances[index].map(|d| d + 1);
+        for (exit, _) in map.get_available_exits(index) {
+            if distances[exit].is_none() {
+                distances[exit] = next_distance;
+                frontier.push_back(exit);
+            }
+        }
+    }
+
+    distances
+}
+
+/// Check that every open tile on the map can be reached from `start`
+pub fn is_connected(map: &Map, start: Point) -> bool {
+    let distances = flood_fill(map, start);
+    map.blocked_tiles
+        .iter()
+        .zip(distances.iter())
+        .all(|(blocked, distance)| *blocked || distance.is_some())
+}
+
+/// Pick `count` different random open tiles, used by the builders that don't have rooms to fill
+/// There may be fewer if the map doesn't have that many open tiles
+fn random_floor_tiles(map: &Map, count: i32, rng: &mut GameRng) -> Vec<Point> {
+    let mut floors: Vec<usize> = map
+        .tiles
+        .iter()
+        .enumerate()
+        .filter(|(_, tile)| **tile == TileType::Floor)
+        .map(|(index, _)| index)
+        .collect();
+
+    rng.shuffle(&mut floors);
+    floors
+        .into_iter()
+        .take(count.max(0) as usize)
+        .map(|index| map.index_to_point2d(index))
+        .collect()
+}
+
+/// The open tile closest to the middle of the map, out of the largest connected area
+/// Builders that leave disconnected pockets start here so that little gets culled
+fn central_floor_tile(map: &mut Map) -> Point {
+    map.set_blocked_tiles();
+    let center = Point::new(map.width / 2, map.height / 2);
+
+    let mut floors: Vec<Point> = map
+        .tiles
+        .iter()
+        .enumerate()
+        .filter(|(_, tile)| **tile == TileType::Floor)
+        .map(|(index, _)| map.index_to_point2d(index))
+        .collect();
+    floors.sort_by_key(|p| (p.x - center.x).abs() + (p.y - center.y).abs());
+
+    let mut visited = vec![false; map.tiles.len()];
+    let mut best: Option<(Point, usize)> = None;
+
+    for floor in floors {
+        if visited[map.point2d_to_index(floor)] {
+            continue;
+        }
+
+        let distances = flood_fill(map, floor);
+        let mut size = 0;
+        for (index, distance) in distances.iter().enumerate() {
+            if distance.is_some() {
+                visited[index] = true;
+                size += 1;
+            }
+        }
+
+        // floors are sorted by distance, so the first tile seen of a region is its most central
+        if best.is_none_or(|(_, best_size)| size > best_size) {
+            best = Some((floor, size));
+        }
+    }
+
+    best.map_or(center, |(start, _)| start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base_builders() -> Vec<(&'static str, Box<dyn MapBuilder>)> {
+        vec![
+            ("rooms", Box::new(RoomsBuilder::default())),
+            ("bsp", Box::new(BspBuilder::default())),
+            ("cellular", Box::new(CellularBuilder::default())),
+            ("drunkard", Box::new(DrunkardBuilder::default())),
+            (
+                "prefab",
+                Box::new(PrefabBuilder::new(Box::new(BspBuilder::default()))),
+            ),
+        ]
+    }
+
+    fn assert_connected(name: &str, builder: &dyn MapBuilder) {
+        for seed in 0..8 {
+            let mut rng = GameRng::new(seed);
+            let built = builder.build(80, 50, 1, &mut rng);
+
+            let start = built.map.point2d_to_index(built.start);
+            assert!(
+                !built.map.blocked_tiles[start],
+                "{} put the start in a wall with seed {}",
+                name, seed
+            );
+            assert!(
+                is_connected(&built.map, built.start),
+                "{} left unreachable tiles with seed {}",
+                name,
+                seed
+            );
+        }
+    }
+
+    #[test]
+    fn every_builder_makes_one_connected_area() {
+        for (name, builder) in base_builders() {
+            assert_connected(name, builder.as_ref());
+        }
+    }
+
+    #[test]
+    fn random_floor_tiles_never_repeat() {
+        let mut map = Map::new(10, 10, 1);
+        map.build_room(rltk::Rect::with_size(1, 1, 3, 3));
+
+        let mut rng = GameRng::new(3);
+        let mut picked = random_floor_tiles(&map, 20, &mut rng);
+        assert_eq!(picked.len(), 16);
+
+        picked.sort_by_key(|tile| (tile.y, tile.x));
+        picked.dedup();
+        assert_eq!(picked.len(), 16);
+    }
+
+    #[test]
+    fn features_keep_every_builder_connected() {
+        for (name, builder) in base_builders() {
+            assert_connected(name, &FeatureBuilder::new(builder));
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use super::{cull_unreachable, reachable_spawns, BuiltMap, MapBuilder};
+use crate::{rng::GameRng, TileType};
+use rltk::Point;
+
+/// A hand-made piece of map
+/// `#` is wall, `.` is floor and `e` is floor with an enemy on it
+pub struct Prefab {
+    pub rows: Vec<&'static str>,
+}
+
+impl Prefab {
+    fn width(&self) -> i32 {
+        self.rows.iter().map(|row| row.len()).max().unwrap_or(0) as i32
+    }
+
+    fn height(&self) -> i32 {
+        self.rows.len() as i32
+    }
+}
+
+/// Stamps prefab vaults into open areas of another builder's map
+pub struct PrefabBuilder {
+    pub base: Box<dyn MapBuilder>,
+    pub vaults: Vec<Prefab>,
+    pub vault_count: i32,
+    /// Random positions tried for each vault before giving up on it
+    pub placement_attempts: i32,
+}
+
+impl PrefabBuilder {
+    pub fn new(base: Box<dyn MapBuilder>) -> PrefabBuilder {
+        PrefabBuilder {
+            base,
+            vaults: default_vaults(),
+            vault_count: 3,
+            placement_attempts: 50,
+        }
+    }
+}
+
+fn default_vaults() -> Vec<Prefab> {
+    vec![
+        Prefab {
+            rows: vec!["#.#", "...", "#.#"],
+        },
+        Prefab {
+            rows: vec!["##.##", "#...#", "..e..", "#...#", "##.##"],
+        },
+        Prefab {
+            rows: vec!["#####.", "#e..#.", "#.#.#.", "#...#.", "##.##."],
+        },
+    ]
+}
+
+impl MapBuilder for PrefabBuilder {
+    fn build(&self, width: i32, height: i32, depth: i32, rng: &mut GameRng) -> BuiltMap {
+        let mut built = self.base.build(width, height, depth, rng);
+        if self.vaults.is_empty() {
+            return built;
+        }
+
+        let start = built.start;
+        let map = &mut built.map;
+        let mut vault_spawns = Vec::new();
+
+        for _ in 0..self.vault_count {
+            let vault = &self.vaults[rng.range(0, self.vaults.len())];
+            let (w, h) = (vault.width(), vault.height());
+            if w + 2 >= width || h + 2 >= height {
+                continue;
+            }
+
+            for _ in 0..self.placement_attempts {
+                let x = rng.range(1, width - w - 1);
+                let y = rng.range(1, height - h - 1);
+
+                // the vault and a ring around it must be plain floor, so that stairs and the
+                // start are never covered and everything outside can still walk around it
+                let fits = (y - 1..=y + h).all(|ty| {
+                    (x - 1..=x + w).all(|tx| {
+                        let index = map.get_index(tx, ty);
+                        map.tiles[index] == TileType::Floor && Point::new(tx, ty) != start
+                    })
+                });
+
+                if !fits {
+                    continue;
+                }
+
+                for (dy, row) in vault.rows.iter().enumerate() {
+                    for (dx, glyph) in row.chars().enumerate() {
+                        let pos = Point::new(x + dx as i32, y + dy as i32);
+                        let index = map.get_index(pos.x, pos.y);
+                        map.tiles[index] = match glyph {
+                            '#' => TileType::Wall,
+                            _ => TileType::Floor,
+                        };
+
+                        if glyph == 'e' {
+                            vault_spawns.push(pos);
+                        }
+                    }
+                }
+
+                break;
+            }
+        }
+
+        // vault walls can seal off their own insides, so check again
+        let distances = cull_unreachable(map, start);
+        let mut spawns = std::mem::take(&mut built.spawns);
+        spawns.extend(vault_spawns);
+        built.spawns = reachable_spawns(map, &distances, start, spawns);
+
+        built
+    }
+}
@@ -0,0 +1,59 @@
+use super::{finalize, BuiltMap, MapBuilder};
+use crate::{rng::GameRng, Map};
+use rltk::Rect;
+
+/// Rectangular rooms placed at random, each joined to the previous one by a corridor
+pub struct RoomsBuilder {
+    pub max_rooms: i32,
+    pub min_room_width: i32,
+    pub max_room_width: i32,
+    pub min_room_height: i32,
+    pub max_room_height: i32,
+}
+
+impl Default for RoomsBuilder {
+    fn default() -> Self {
+        RoomsBuilder {
+            max_rooms: 30,
+            min_room_width: 3,
+            max_room_width: 12,
+            min_room_height: 3,
+            max_room_height: 12,
+        }
+    }
+}
+
+impl MapBuilder for RoomsBuilder {
+    fn build(&self, width: i32, height: i32, depth: i32, rng: &mut GameRng) -> BuiltMap {
+        let mut map = Map::new(width, height, depth);
+
+        for _ in 0..self.max_rooms {
+            let w = rng.range(self.min_room_width, self.max_room_width);
+            let h = rng.range(self.min_room_height, self.max_room_height);
+            let x = rng.range(1, map.width - w - 1);
+            let y = rng.range(1, map.height - h - 1);
+
+            let new_room = Rect::with_size(x, y, w, h);
+            if map.rooms.iter().any(|other| other.intersect(&new_room)) {
+                continue;
+            }
+
+            map.build_room(new_room);
+            if map.rooms.len() > 1 {
+                let new_center = new_room.center();
+                let prev_center = map.rooms[map.rooms.len() - 2].center();
+
+                if rng.rand::<f32>() > 0.5 {
+                    map.build_hallway(prev_center, new_center);
+                } else {
+                    map.build_hallway(new_center, prev_center);
+                }
+            }
+        }
+
+        // the player starts in the first room, and everything else gets an enemy
+        let start = map.rooms[0].center();
+        let spawns = map.rooms.iter().skip(1).map(|room| room.center()).collect();
+        finalize(map, start, spawns)
+    }
+}
@@ -1,23 +1,190 @@
-use super::{AttackIntent, Map, MoveIntent, Player, Position, RunState, State};
-use rltk::{Rltk, VirtualKeyCode};
+use super::gamelog::GameLog;
+use super::raws::{self, RawMaster};
+use super::{
+    dungeon, spawner, sys_attack, Ability, Ai, Aim, AttackIntent, BumpAttack, EventType, Faction,
+    Guarding, Health, Inventory, Item, Map, MoveIntent, Moveset, Name, Player, Position,
+    ReactIntent, ReactionType, RunState, Schedulable, StatusEffects, TileType, Viewshed, MOVE_COST,
+    REACTION_COST, WAIT_COST,
+};
+use rltk::{Algorithm2D, Point, Rltk, VirtualKeyCode};
 use specs::prelude::*;
+use std::fmt;
+use std::str::FromStr;
+
+/// Everything the player can do, independent of where the input came from
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Command {
+    Move {
+        dx: i32,
+        dy: i32,
+    },
+    ToggleDoor {
+        dx: i32,
+        dy: i32,
+    },
+    UseAbility {
+        slot: usize,
+        dx: i32,
+        dy: i32,
+    },
+    /// The ranged ability, whichever slot it is in, see ranged_slot
+    Fire {
+        dx: i32,
+        dy: i32,
+    },
+    UseItem {
+        slot: usize,
+        dx: i32,
+        dy: i32,
+    },
+    PickUp,
+    Drop {
+        slot: usize,
+    },
+    Wait,
+    Dodge {
+        dx: i32,
+        dy: i32,
+    },
+    Counter {
+        dx: i32,
+        dy: i32,
+    },
+    Parry,
+    Brace,
+    TakeHit,
+    Descend,
+    Ascend,
+}
+
+impl Command {
+    fn is_reaction(&self) -> bool {
+        !matches!(
+            self,
+            Command::Move { .. }
+                | Command::ToggleDoor { .. }
+                | Command::UseAbility { .. }
+                | Command::Fire { .. }
+                | Command::UseItem { .. }
+                | Command::PickUp
+                | Command::Drop { .. }
+                | Command::Wait
+                | Command::Descend
+                | Command::Ascend
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseCommandError(String);
+
+impl fmt::Display for ParseCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unrecognized command '{}'", self.0)
+    }
+}
+
+impl std::error::Error for ParseCommandError {}
+
+/// Commands are written as a name, optionally followed by numbers, e.g. `move:1,0` or `parry`
+/// Abilities are named by their number key, so `ability:2,3,0` uses the second one 3 tiles right
+/// `fire:3,0` does the same with the ranged ability, without needing to know its key
+/// Items are numbered from 1 in the order they were picked up, e.g. `item:1,0,0` or `drop:2`
+impl FromStr for Command {
+    type Err = ParseCommandError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseCommandError(s.to_string());
+        let (name, args) = match s.find(':') {
+            None => (s, None),
+            Some(split) => (&s[..split], Some(&s[split + 1..])),
+        };
+
+        let numbers = match args {
+            None => Vec::new(),
+            Some(args) => args
+                .split(',')
+                .map(|part| part.trim().parse::<i32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| err())?,
+        };
+
+        match (name.trim(), numbers.as_slice()) {
+            ("move", &[dx, dy]) => Ok(Command::Move { dx, dy }),
+            ("door", &[dx, dy]) => Ok(Command::ToggleDoor { dx, dy }),
+            ("ability", &[key, dx, dy]) if key >= 1 => Ok(Command::UseAbility {
+                slot: (key - 1) as usize,
+                dx,
+                dy,
+            }),
+            ("fire", &[dx, dy]) => Ok(Command::Fire { dx, dy }),
+            ("item", &[key, dx, dy]) if key >= 1 => Ok(Command::UseItem {
+                slot: (key - 1) as usize,
+                dx,
+                dy,
+            }),
+            ("pickup", []) => Ok(Command::PickUp),
+            ("drop", &[key]) if key >= 1 => Ok(Command::Drop {
+                slot: (key - 1) as usize,
+            }),
+            ("dodge", &[dx, dy]) => Ok(Command::Dodge { dx, dy }),
+            ("counter", &[dx, dy]) => Ok(Command::Counter { dx, dy }),
+            ("parry", []) => Ok(Command::Parry),
+            ("brace", []) => Ok(Command::Brace),
+            ("pass", []) => Ok(Command::TakeHit),
+            ("wait", []) => Ok(Command::Wait),
+            ("descend", []) => Ok(Command::Descend),
+            ("ascend", []) => Ok(Command::Ascend),
+            _ => Err(err()),
+        }
+    }
+}
+
+/// Parse a whitespace separated list of commands
+pub fn parse_script(script: &str) -> Result<Vec<Command>, ParseCommandError> {
+    script.split_whitespace().map(Command::from_str).collect()
+}
 
 fn try_move_player(ecs: &mut World, dx: i32, dy: i32) -> RunState {
     use std::cmp::{max, min};
+    let ally = ally_at_offset(ecs, dx, dy);
     let mut positions = ecs.write_storage::<Position>();
     let players = ecs.read_storage::<Player>();
     let mut movements = ecs.write_storage::<MoveIntent>();
     let mut attacks = ecs.write_storage::<AttackIntent>();
+    let bump_attacks = ecs.read_storage::<BumpAttack>();
     let map = ecs.fetch::<Map>();
     let player = ecs.fetch::<Entity>();
+    let mut swapped = None;
+
+    for (_player, pos, bump) in (&players, &mut positions, (&bump_attacks).maybe()).join() {
+        // the same rules as monster pathing, so nobody gets to slip around corners the other can't
+        if !map.is_step_allowed(pos.as_point(), dx, dy) {
+            continue;
+        }
 
-    for (_player, pos) in (&players, &mut positions).join() {
         let dest_index = map.get_index(pos.x + dx, pos.y + dy);
 
         let new_x = min(map.width, max(0, pos.x + dx));
         let new_y = min(map.height, max(0, pos.y + dy));
 
-        if !map.blocked_tiles[dest_index] {
+        // allies get out of the way by trading places, once the step itself is allowed
+        if let Some(ally) = ally {
+            movements
+                .insert(
+                    *player,
+                    MoveIntent {
+                        loc: rltk::Point::new(new_x, new_y),
+                    },
+                )
+                .expect("Failed to insert new movement from player");
+
+            swapped = Some((ally, pos.as_point()));
+            break;
+        }
+
+        // closed doors are opened by walking into them, see MovementSystem
+        if !map.blocked_tiles[dest_index] || map.tiles[dest_index] == TileType::DoorClosed {
             let new_move = MoveIntent {
                 loc: rltk::Point::new(new_x, new_y),
             };
@@ -26,10 +193,16 @@ fn try_move_player(ecs: &mut World, dx: i32, dy: i32) -> RunState {
                 .expect("Failed to insert new movement from player");
 
             return RunState::Running;
-        } else if map.tiles[dest_index] != crate::TileType::Wall {
+        } else if let Some(bump) = bump {
+            // walls only take a swing if they can be broken
+            let tile = map.tiles[dest_index];
+            if tile.blocks_movement() && !tile.is_destructible() {
+                continue;
+            }
+
             let new_attack = AttackIntent {
                 loc: rltk::Point::new(new_x, new_y),
-                range: crate::RangeType::Single,
+                attack: bump.attack.clone(),
             };
             attacks
                 .insert(*player, new_attack)
@@ -39,31 +212,653 @@ fn try_move_player(ecs: &mut World, dx: i32, dy: i32) -> RunState {
         }
     }
 
+    // the ally is shoved into the player's place rather than taking a step, so it pays nothing
+    if let Some((ally, loc)) = swapped {
+        if let Some(pos) = positions.get_mut(ally) {
+            pos.x = loc.x;
+            pos.y = loc.y;
+        }
+        if let Some(viewshed) = ecs.write_storage::<Viewshed>().get_mut(ally) {
+            viewshed.dirty = true;
+        }
+
+        return RunState::Running;
+    }
+
     RunState::AwaitingInput
 }
 
-pub fn player_input(gs: &mut State, ctx: &mut Rltk) -> RunState {
-    let is_reaction: bool;
+/// A monster on the player's side standing next to the player in the given direction
+fn ally_at_offset(ecs: &World, dx: i32, dy: i32) -> Option<Entity> {
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let factions = ecs.read_storage::<Faction>();
+    let ais = ecs.read_storage::<Ai>();
+    let player = ecs.fetch::<Entity>();
+
+    let pos = positions.get(*player)?;
+    let dest = rltk::Point::new(pos.x + dx, pos.y + dy);
+    let side = factions.get(*player)?;
+
+    (&entities, &positions, &factions, &ais)
+        .join()
+        .find(|(_, other_pos, faction, _)| {
+            other_pos.as_point() == dest && faction.name == side.name
+        })
+        .map(|(ent, _, _, _)| ent)
+}
+
+/// Open or close the door next to the player
+/// Doors can't be closed on anything standing in the doorway
+fn try_toggle_door(ecs: &mut World, dx: i32, dy: i32) -> RunState {
+    let positions = ecs.read_storage::<Position>();
+    let mut viewsheds = ecs.write_storage::<Viewshed>();
+    let mut map = ecs.fetch_mut::<Map>();
+    let player = ecs.fetch::<Entity>();
+
+    let pos = positions
+        .get(*player)
+        .expect("Player does not have a Position");
+    let dest = rltk::Point::new(pos.x + dx, pos.y + dy);
+    if !map.in_bounds(dest) {
+        return RunState::AwaitingInput;
+    }
+
+    let dest_index = map.get_index(dest.x, dest.y);
+    match map.tiles[dest_index] {
+        TileType::DoorClosed => {
+            map.tiles[dest_index] = TileType::DoorOpen;
+            map.blocked_tiles[dest_index] = false;
+        }
+        TileType::DoorOpen => {
+            if map.blocked_tiles[dest_index] || positions.join().any(|pos| pos.as_point() == dest) {
+                return RunState::AwaitingInput;
+            }
+
+            map.tiles[dest_index] = TileType::DoorClosed;
+            map.blocked_tiles[dest_index] = true;
+        }
+        _ => return RunState::AwaitingInput,
+    }
+
+    for viewshed in (&mut viewsheds).join() {
+        viewshed.dirty = true;
+    }
+
+    RunState::Running
+}
+
+/// The tile `dx` and `dy` away from the player, if `ability` can be used on it right now
+/// It has to be ready, in reach, and aimed at a tile the player can see
+fn aim_at(ecs: &World, ability: &Ability, dx: i32, dy: i32) -> Option<Point> {
+    let map = ecs.fetch::<Map>();
+    let pos = ecs
+        .read_storage::<Position>()
+        .get(*ecs.fetch::<Entity>())
+        .expect("Player does not have a Position")
+        .as_point();
+
+    let dest = pos + Point::new(dx, dy);
+    if !ability.is_ready()
+        || !ability.reaches(pos, dest)
+        || !map.in_bounds(dest)
+        || !map.visible_tiles[map.point2d_to_index(dest)]
     {
-        // we expect it to be our turn
-        let can_act = gs.ecs.read_storage::<super::CanActFlag>();
-        let player = gs.ecs.fetch::<Entity>();
-        is_reaction = can_act
+        return None;
+    }
+
+    // close up abilities follow the same rules about corners as bumping
+    if ability.reach == 1 && !map.is_step_allowed(pos, dx, dy) {
+        return None;
+    }
+
+    Some(dest)
+}
+
+/// What the player would be using for `aim`, with items standing in as abilities
+pub fn aimed_ability(ecs: &World, aim: Aim) -> Option<Ability> {
+    let player = ecs.fetch::<Entity>();
+    match aim {
+        Aim::Ability(slot) => ecs
+            .read_storage::<Moveset>()
+            .get(*player)
+            .and_then(|moveset| moveset.abilities.get(slot))
+            .cloned(),
+        Aim::Item(slot) => {
+            let inventories = ecs.read_storage::<Inventory>();
+            let id = inventories.get(*player)?.items.get(slot)?;
+            let raws = ecs.fetch::<RawMaster>();
+            raws.get_item(id).ok().map(|item| item.as_ability())
+        }
+    }
+}
+
+fn aim_command(aim: Aim, dx: i32, dy: i32) -> Command {
+    match aim {
+        Aim::Ability(slot) => Command::UseAbility { slot, dx, dy },
+        Aim::Item(slot) => Command::UseItem { slot, dx, dy },
+    }
+}
+
+/// The first of the player's abilities that shoots a projectile, which F and `fire:` aim
+fn ranged_slot(ecs: &World) -> Option<usize> {
+    let raws = ecs.fetch::<RawMaster>();
+    ecs.read_storage::<Moveset>()
+        .get(*ecs.fetch::<Entity>())?
+        .abilities
+        .iter()
+        .position(|ability| {
+            raws.get_attack(&ability.attack)
+                .is_some_and(|attack_def| attack_def.projectile.is_some())
+        })
+}
+
+fn try_use_ability(ecs: &mut World, slot: usize, dx: i32, dy: i32) -> RunState {
+    let ability = match aimed_ability(ecs, Aim::Ability(slot)) {
+        None => return RunState::AwaitingInput,
+        Some(ability) => ability,
+    };
+    let dest = match aim_at(ecs, &ability, dx, dy) {
+        None => return RunState::AwaitingInput,
+        Some(dest) => dest,
+    };
+
+    let player = ecs.fetch::<Entity>();
+    if let Some(moveset) = ecs.write_storage::<Moveset>().get_mut(*player) {
+        moveset.abilities[slot].remaining = ability.cooldown;
+    }
+
+    ecs.write_storage::<AttackIntent>()
+        .insert(
+            *player,
+            AttackIntent {
+                loc: dest,
+                attack: ability.attack,
+            },
+        )
+        .expect("Failed to insert new attack from player");
+
+    RunState::Running
+}
+
+/// Use up the item in `slot`, making its attack on the tile `dx` and `dy` away from the player
+fn try_use_item(ecs: &mut World, slot: usize, dx: i32, dy: i32) -> RunState {
+    let ability = match aimed_ability(ecs, Aim::Item(slot)) {
+        None => return RunState::AwaitingInput,
+        Some(ability) => ability,
+    };
+    let dest = match aim_at(ecs, &ability, dx, dy) {
+        None => return RunState::AwaitingInput,
+        Some(dest) => dest,
+    };
+
+    if is_wasted_heal(ecs, &ability.attack, dest) {
+        ecs.fetch_mut::<GameLog>()
+            .entries
+            .push("Nobody there needs healing".to_string());
+        return RunState::AwaitingInput;
+    }
+
+    let player = ecs.fetch::<Entity>();
+    let id = ecs
+        .write_storage::<Inventory>()
+        .get_mut(*player)
+        .expect("Player does not have an Inventory")
+        .items
+        .remove(slot);
+
+    if let Ok(item) = ecs.fetch::<RawMaster>().get_item(&id) {
+        ecs.fetch_mut::<GameLog>()
+            .entries
+            .push(format!("You use the {}", item.name));
+    }
+
+    // the attack goes on the event stack like any other, so others get to react to it
+    ecs.write_storage::<AttackIntent>()
+        .insert(
+            *player,
+            AttackIntent {
+                loc: dest,
+                attack: ability.attack,
+            },
+        )
+        .expect("Failed to insert new attack from player");
+
+    RunState::Running
+}
+
+/// Whether a heal aimed at `dest` would land on nobody who is hurt, using up the item for nothing
+fn is_wasted_heal(ecs: &World, attack: &str, dest: Point) -> bool {
+    let raws = ecs.fetch::<RawMaster>();
+    let attack_def = match raws.get_attack(attack) {
+        Some(attack_def) if matches!(attack_def.event, EventType::Heal { .. }) => attack_def,
+        _ => return false,
+    };
+
+    let (_, tiles) = sys_attack::preview_attack(ecs, *ecs.fetch::<Entity>(), attack_def, dest);
+    let positions = ecs.read_storage::<Position>();
+    let healths = ecs.read_storage::<Health>();
+
+    !(&positions, &healths).join().any(|(pos, health)| {
+        tiles.contains(&pos.as_point()) && health.current > 0 && health.current < health.max
+    })
+}
+
+/// Pick up an item the player is standing on, if there is room for it
+fn try_pick_up(ecs: &mut World) -> RunState {
+    let picked = {
+        let entities = ecs.entities();
+        let positions = ecs.read_storage::<Position>();
+        let items = ecs.read_storage::<Item>();
+        let names = ecs.read_storage::<Name>();
+        let mut inventories = ecs.write_storage::<Inventory>();
+        let mut log = ecs.fetch_mut::<GameLog>();
+        let player = ecs.fetch::<Entity>();
+
+        let pos = positions
             .get(*player)
-            .expect("player_input called, but it is not your turn")
-            .is_reaction;
+            .expect("Player does not have a Position")
+            .as_point();
+        let (ent, item, name) = match (&entities, &positions, &items, &names)
+            .join()
+            .find(|(_, item_pos, _, _)| item_pos.as_point() == pos)
+        {
+            None => return RunState::AwaitingInput,
+            Some((ent, _, item, name)) => (ent, item, name),
+        };
+
+        let inventory = match inventories.get_mut(*player) {
+            None => return RunState::AwaitingInput,
+            Some(inventory) => inventory,
+        };
+        if inventory.items.len() >= inventory.capacity {
+            log.entries.push("You can't carry any more".to_string());
+            return RunState::AwaitingInput;
+        }
+
+        inventory.items.push(item.id.clone());
+        log.entries.push(format!("You pick up the {}", name.name));
+        ent
+    };
+
+    ecs.delete_entity(picked)
+        .expect("Failed to remove picked up item");
+    RunState::Running
+}
+
+/// Put the item in `slot` down where the player is standing
+fn try_drop(ecs: &mut World, slot: usize) -> RunState {
+    let player = *ecs.fetch::<Entity>();
+    let id = match ecs
+        .read_storage::<Inventory>()
+        .get(player)
+        .and_then(|inventory| inventory.items.get(slot))
+    {
+        None => return RunState::AwaitingInput,
+        Some(id) => id.clone(),
+    };
+    let pos = ecs
+        .read_storage::<Position>()
+        .get(player)
+        .expect("Player does not have a Position")
+        .as_point();
+
+    let (dropped, message) = raws::with_raws(ecs, |raws, ecs| {
+        let message = raws
+            .get_item(&id)
+            .map(|item| format!("You drop the {}", item.name));
+        (spawner::item(ecs, raws, &id, pos.x, pos.y), message)
+    });
+
+    // the item only leaves the inventory once it is safely on the floor
+    if dropped.is_err() {
+        return RunState::AwaitingInput;
+    }
+
+    if let Some(inventory) = ecs.write_storage::<Inventory>().get_mut(player) {
+        inventory.items.remove(slot);
+    }
+    if let Ok(message) = message {
+        ecs.fetch_mut::<GameLog>().entries.push(message);
     }
 
-    let result = handle_keys(gs, ctx, is_reaction);
+    RunState::Running
+}
+
+fn try_change_level(ecs: &mut World, delta: i32) -> RunState {
+    match dungeon::change_level(ecs, delta) {
+        Ok(true) => RunState::Running,
+        Ok(false) => RunState::AwaitingInput,
+        Err(err) => {
+            // the level change itself went through, only some of the spawns failed
+            ecs.fetch_mut::<GameLog>()
+                .entries
+                .push(format!("Failed to populate the level: {}", err));
+            RunState::Running
+        }
+    }
+}
+
+fn try_react(ecs: &mut World, reaction: ReactionType, dx: i32, dy: i32) -> RunState {
+    let positions = ecs.read_storage::<Position>();
+    let mut reactions = ecs.write_storage::<ReactIntent>();
+    let map = ecs.fetch::<Map>();
+    let player = ecs.fetch::<Entity>();
+
+    let pos = positions
+        .get(*player)
+        .expect("Player does not have a Position");
+    let dest = rltk::Point::new(pos.x + dx, pos.y + dy);
+
+    if dest.x < 0 || dest.x >= map.width || dest.y < 0 || dest.y >= map.height {
+        return RunState::AwaitingInput;
+    }
+
+    // dodging needs an open tile to land on, reachable in a single step
+    let dest_index = map.get_index(dest.x, dest.y);
+    if reaction == ReactionType::Dodge
+        && (map.blocked_tiles[dest_index] || !map.is_step_allowed(pos.as_point(), dx, dy))
+    {
+        return RunState::AwaitingInput;
+    }
+
+    reactions
+        .insert(
+            *player,
+            ReactIntent {
+                reaction,
+                loc: dest,
+            },
+        )
+        .expect("Failed to insert new reaction from player");
+
+    RunState::Running
+}
+
+/// One frame of input, read from the window or made up by a test
+#[derive(PartialEq, Copy, Clone, Debug, Default)]
+pub struct Input {
+    pub key: Option<VirtualKeyCode>,
+    pub shift: bool,
+    /// The tile under the mouse, only if the mouse moved or clicked this frame
+    pub mouse: Option<Point>,
+    pub click: bool,
+}
+
+impl Input {
+    /// Read this frame's input, comparing the mouse against `last_mouse` and then updating it
+    pub fn read(ctx: &Rltk, last_mouse: &mut Point) -> Input {
+        let mouse = ctx.mouse_point();
+        let moved = mouse != *last_mouse;
+        *last_mouse = mouse;
+
+        Input {
+            key: ctx.key,
+            shift: ctx.shift,
+            mouse: if moved || ctx.left_click {
+                Some(mouse)
+            } else {
+                None
+            },
+            click: ctx.left_click,
+        }
+    }
+}
+
+pub fn player_input(ecs: &mut World, input: &Input) -> RunState {
+    let is_reaction = is_reaction_turn(ecs);
+
+    if !is_reaction {
+        if let Some(slot) = input.key.and_then(slot_from_key) {
+            return begin_targeting(ecs, Aim::Ability(slot));
+        }
+
+        if input.key == Some(VirtualKeyCode::F) {
+            return match ranged_slot(ecs) {
+                None => RunState::AwaitingInput,
+                Some(slot) => begin_targeting(ecs, Aim::Ability(slot)),
+            };
+        }
+
+        if input.key == Some(VirtualKeyCode::I) {
+            return RunState::Inventory;
+        }
+    }
+
+    match input
+        .key
+        .and_then(|key| command_from_key(key, input.shift, is_reaction))
+    {
+        None => RunState::AwaitingInput,
+        Some(command) => perform_command(ecs, command),
+    }
+}
+
+/// Start aiming, with the cursor on the closest enemy in reach
+/// Anything used where the player stands doesn't need aiming, so it goes off right away
+fn begin_targeting(ecs: &mut World, aim: Aim) -> RunState {
+    let ability = match aimed_ability(ecs, aim) {
+        Some(ability) if ability.is_ready() => ability,
+        _ => return RunState::AwaitingInput,
+    };
+
+    if ability.reach == 0 {
+        return perform_command(ecs, aim_command(aim, 0, 0));
+    }
+
+    let pos = ecs
+        .read_storage::<Position>()
+        .get(*ecs.fetch::<Entity>())
+        .expect("Player does not have a Position")
+        .as_point();
+    let cursor = targets_in_reach(ecs, &ability)
+        .first()
+        .copied()
+        .unwrap_or(pos);
+
+    RunState::Targeting { aim, cursor }
+}
+
+/// Where the visible enemies in reach of `ability` stand, closest first
+fn targets_in_reach(ecs: &World, ability: &Ability) -> Vec<Point> {
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let healths = ecs.read_storage::<Health>();
+    let factions = ecs.read_storage::<Faction>();
+    let map = ecs.fetch::<Map>();
+    let raws = ecs.fetch::<RawMaster>();
+    let player = ecs.fetch::<Entity>();
+
+    let pos = positions
+        .get(*player)
+        .expect("Player does not have a Position")
+        .as_point();
+    let side = factions.get(*player).map(|faction| faction.name.as_str());
+
+    let mut targets: Vec<(i32, u32, Point)> = (&entities, &positions, &healths, &factions)
+        .join()
+        .filter(|(_, other_pos, _, faction)| {
+            side.is_some_and(|side| raws.is_hostile(side, &faction.name))
+                && ability.reaches(pos, other_pos.as_point())
+                && map.visible_tiles[map.point2d_to_index(other_pos.as_point())]
+        })
+        .map(|(other, other_pos, _, _)| {
+            let distance =
+                rltk::DistanceAlg::PythagorasSquared.distance2d(pos, other_pos.as_point());
+            (distance as i32, other.id(), other_pos.as_point())
+        })
+        .collect();
+    targets.sort_by_key(|(distance, id, _)| (*distance, *id));
+
+    targets.into_iter().map(|(_, _, target)| target).collect()
+}
+
+/// Move the targeting cursor with the direction keys or the mouse, then confirm or back out
+/// Tab cycles through the enemies in reach, and clicking confirms straight away on the tile under
+/// the mouse
+pub fn targeting_input(ecs: &mut World, input: &Input, aim: Aim, cursor: Point) -> RunState {
+    let mut cursor = cursor;
+    let pointed = input
+        .mouse
+        .filter(|mouse| ecs.fetch::<Map>().in_bounds(*mouse));
+    if let Some(mouse) = pointed {
+        cursor = mouse;
+    }
+
+    let confirmed = match input.key {
+        Some(VirtualKeyCode::Escape) => return RunState::AwaitingInput,
+        Some(VirtualKeyCode::Return) => true,
+        // pressing an ability's key again uses it too
+        Some(key) if slot_from_key(key).map(Aim::Ability) == Some(aim) => true,
+        Some(VirtualKeyCode::F) if ranged_slot(ecs).map(Aim::Ability) == Some(aim) => true,
+        Some(VirtualKeyCode::Tab) => {
+            let targets = aimed_ability(ecs, aim)
+                .map_or(Vec::new(), |ability| targets_in_reach(ecs, &ability));
+            let next = targets
+                .iter()
+                .position(|target| *target == cursor)
+                .map_or(0, |index| index + 1);
+            if let Some(target) = targets.get(next).or(targets.first()) {
+                cursor = *target;
+            }
+            false
+        }
+        Some(key) => {
+            if let Some((dx, dy)) = direction_from_key(key) {
+                let moved = cursor + Point::new(dx, dy);
+                if ecs.fetch::<Map>().in_bounds(moved) {
+                    cursor = moved;
+                }
+            }
+            false
+        }
+        None => pointed.is_some() && input.click,
+    };
+
+    if confirmed {
+        let pos = ecs
+            .read_storage::<Position>()
+            .get(*ecs.fetch::<Entity>())
+            .expect("Player does not have a Position")
+            .as_point();
+        let offset = cursor - pos;
+
+        if perform_command(ecs, aim_command(aim, offset.x, offset.y)) == RunState::Running {
+            return RunState::Running;
+        }
+    }
+
+    RunState::Targeting { aim, cursor }
+}
+
+/// Pick an item by its letter to use it, or hold shift to drop it instead
+pub fn inventory_input(ecs: &mut World, input: &Input) -> RunState {
+    let key = match input.key {
+        None => return RunState::Inventory,
+        Some(VirtualKeyCode::Escape) => return RunState::AwaitingInput,
+        Some(key) => key,
+    };
+
+    let count = ecs
+        .read_storage::<Inventory>()
+        .get(*ecs.fetch::<Entity>())
+        .map_or(0, |inventory| inventory.items.len());
+    let slot = rltk::letter_to_option(key);
+    if slot < 0 || slot as usize >= count {
+        // I closes the screen again, unless there is a ninth item for it to pick
+        return match key {
+            VirtualKeyCode::I => RunState::AwaitingInput,
+            _ => RunState::Inventory,
+        };
+    }
+
+    let slot = slot as usize;
+    if input.shift {
+        perform_command(ecs, Command::Drop { slot })
+    } else {
+        begin_targeting(ecs, Aim::Item(slot))
+    }
+}
+
+fn is_reaction_turn(ecs: &World) -> bool {
+    // we expect it to be our turn
+    let can_act = ecs.read_storage::<super::CanActFlag>();
+    let player = ecs.fetch::<Entity>();
+    can_act
+        .get(*player)
+        .expect("player_input called, but it is not your turn")
+        .is_reaction
+}
+
+/// Carry out a command for the player, returning AwaitingInput if it could not be done right now
+pub fn perform_command(ecs: &mut World, command: Command) -> RunState {
+    let is_reaction = is_reaction_turn(ecs);
+    if command.is_reaction() != is_reaction {
+        return RunState::AwaitingInput;
+    }
+
+    let result = match command {
+        Command::Move { dx, dy } => try_move_player(ecs, dx, dy),
+        Command::ToggleDoor { dx, dy } => try_toggle_door(ecs, dx, dy),
+        Command::UseAbility { slot, dx, dy } => try_use_ability(ecs, slot, dx, dy),
+        Command::Fire { dx, dy } => match ranged_slot(ecs) {
+            None => RunState::AwaitingInput,
+            Some(slot) => try_use_ability(ecs, slot, dx, dy),
+        },
+        Command::UseItem { slot, dx, dy } => try_use_item(ecs, slot, dx, dy),
+        Command::PickUp => try_pick_up(ecs),
+        Command::Drop { slot } => try_drop(ecs, slot),
+        Command::Wait => RunState::Running,
+        Command::Dodge { dx, dy } => try_react(ecs, ReactionType::Dodge, dx, dy),
+        Command::Counter { dx, dy } => try_react(ecs, ReactionType::Counter, dx, dy),
+        Command::Parry => try_react(ecs, ReactionType::Parry, 0, 0),
+        Command::Brace => try_react(ecs, ReactionType::Brace, 0, 0),
+        Command::TakeHit => RunState::Running,
+        Command::Descend => try_change_level(ecs, 1),
+        Command::Ascend => try_change_level(ecs, -1),
+    };
 
     if result == RunState::Running {
-        update_reaction_state(&mut gs.ecs, is_reaction);
-        clear_lingering_cards(&mut gs.ecs);
+        spend_energy(ecs, command);
+        update_reaction_state(ecs, is_reaction);
+        clear_lingering_cards(ecs);
     }
 
     result
 }
 
+/// Charge the player's scheduler for a command that went through
+fn spend_energy(ecs: &mut World, command: Command) {
+    let player = ecs.fetch::<Entity>();
+    let cost = match command {
+        // bumping into something turns the move into an attack
+        Command::Move { .. } => match ecs.read_storage::<AttackIntent>().get(*player) {
+            Some(attack) => ecs.fetch::<RawMaster>().attack_cost(&attack.attack),
+            None => MOVE_COST,
+        },
+        Command::UseAbility { .. } | Command::Fire { .. } | Command::UseItem { .. } => {
+            match ecs.read_storage::<AttackIntent>().get(*player) {
+                Some(attack) => ecs.fetch::<RawMaster>().attack_cost(&attack.attack),
+                None => 0,
+            }
+        }
+        Command::ToggleDoor { .. }
+        | Command::PickUp
+        | Command::Drop { .. }
+        | Command::Descend
+        | Command::Ascend => MOVE_COST,
+        Command::Wait => WAIT_COST,
+        Command::Dodge { .. } | Command::Counter { .. } | Command::Parry | Command::Brace => {
+            REACTION_COST
+        }
+        Command::TakeHit => 0,
+    };
+
+    if let Some(sched) = ecs.write_storage::<Schedulable>().get_mut(*player) {
+        sched.charge(cost, ecs.read_storage::<StatusEffects>().get(*player));
+    }
+}
+
 // if we are in a reaction, remove the CanReact flag
 // otherwise, we are on the main turn, so restore the flag
 fn update_reaction_state(ecs: &mut World, is_reaction: bool) {
@@ -77,6 +872,10 @@ fn update_reaction_state(ecs: &mut World, is_reaction: bool) {
         can_react
             .insert(*player, super::CanReactFlag {})
             .expect("Failed to insert CanReactFlag");
+
+        // guards from the last reaction only last until our next turn
+        let mut guards = ecs.write_storage::<Guarding>();
+        guards.remove(*player);
     }
 
     can_act.clear();
@@ -87,23 +886,61 @@ fn clear_lingering_cards(ecs: &mut World) {
     cards.clear();
 }
 
-fn handle_keys(gs: &mut State, ctx: &mut Rltk, _is_reaction: bool) -> RunState {
-    match ctx.key {
-        None => RunState::AwaitingInput,
-        Some(key) => match key {
-            VirtualKeyCode::Left | VirtualKeyCode::Numpad4 | VirtualKeyCode::H => {
-                try_move_player(&mut gs.ecs, -1, 0)
-            }
-            VirtualKeyCode::Right | VirtualKeyCode::Numpad6 | VirtualKeyCode::L => {
-                try_move_player(&mut gs.ecs, 1, 0)
-            }
-            VirtualKeyCode::Up | VirtualKeyCode::Numpad8 | VirtualKeyCode::K => {
-                try_move_player(&mut gs.ecs, 0, -1)
-            }
-            VirtualKeyCode::Down | VirtualKeyCode::Numpad2 | VirtualKeyCode::J => {
-                try_move_player(&mut gs.ecs, 0, 1)
-            }
-            _ => RunState::AwaitingInput,
-        },
+fn direction_from_key(key: VirtualKeyCode) -> Option<(i32, i32)> {
+    match key {
+        VirtualKeyCode::Left | VirtualKeyCode::Numpad4 | VirtualKeyCode::H => Some((-1, 0)),
+        VirtualKeyCode::Right | VirtualKeyCode::Numpad6 | VirtualKeyCode::L => Some((1, 0)),
+        VirtualKeyCode::Up | VirtualKeyCode::Numpad8 | VirtualKeyCode::K => Some((0, -1)),
+        VirtualKeyCode::Down | VirtualKeyCode::Numpad2 | VirtualKeyCode::J => Some((0, 1)),
+        VirtualKeyCode::Numpad7 | VirtualKeyCode::Y => Some((-1, -1)),
+        VirtualKeyCode::Numpad9 | VirtualKeyCode::U => Some((1, -1)),
+        VirtualKeyCode::Numpad1 | VirtualKeyCode::B => Some((-1, 1)),
+        VirtualKeyCode::Numpad3 | VirtualKeyCode::N => Some((1, 1)),
+        _ => None,
+    }
+}
+
+/// The number keys pick abilities, with 1 being the first
+fn slot_from_key(key: VirtualKeyCode) -> Option<usize> {
+    match key {
+        VirtualKeyCode::Key1 => Some(0),
+        VirtualKeyCode::Key2 => Some(1),
+        VirtualKeyCode::Key3 => Some(2),
+        VirtualKeyCode::Key4 => Some(3),
+        VirtualKeyCode::Key5 => Some(4),
+        VirtualKeyCode::Key6 => Some(5),
+        VirtualKeyCode::Key7 => Some(6),
+        VirtualKeyCode::Key8 => Some(7),
+        VirtualKeyCode::Key9 => Some(8),
+        _ => None,
+    }
+}
+
+fn command_from_key(key: VirtualKeyCode, shift: bool, is_reaction: bool) -> Option<Command> {
+    if let Some((dx, dy)) = direction_from_key(key) {
+        return Some(match (is_reaction, shift) {
+            (false, false) => Command::Move { dx, dy },
+            (false, true) => Command::ToggleDoor { dx, dy },
+            (true, false) => Command::Dodge { dx, dy },
+            (true, true) => Command::Counter { dx, dy },
+        });
+    }
+
+    if !is_reaction {
+        return match (key, shift) {
+            (VirtualKeyCode::Space, _) | (VirtualKeyCode::Period, false) => Some(Command::Wait),
+            (VirtualKeyCode::Period, true) => Some(Command::Descend),
+            (VirtualKeyCode::Comma, true) => Some(Command::Ascend),
+            (VirtualKeyCode::G, false) => Some(Command::PickUp),
+            _ => None,
+        };
+    }
+
+    match key {
+        VirtualKeyCode::P => Some(Command::Parry),
+        VirtualKeyCode::F => Some(Command::Brace),
+        // take the hit
+        VirtualKeyCode::Space | VirtualKeyCode::Period => Some(Command::TakeHit),
+        _ => None,
     }
 }
@@ -0,0 +1,526 @@
+use super::{Ability, Behavior, EventType, ParticleRequest, RangeType, TargetingPolicy};
+use rltk::{Point, RGB};
+use serde::{Deserialize, Serialize};
+use specs::World;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Deserialize)]
+struct RawFile {
+    attacks: Vec<AttackDef>,
+    #[serde(default)]
+    monsters: Vec<MonsterDef>,
+    #[serde(default)]
+    factions: Vec<FactionDef>,
+    #[serde(default)]
+    items: Vec<ItemDef>,
+    #[serde(default)]
+    player_moveset: Vec<Ability>,
+}
+
+/// Hostility goes both ways, so it only needs listing on one of the two factions
+#[derive(Deserialize)]
+struct FactionDef {
+    name: String,
+    #[serde(default)]
+    hostile_to: Vec<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct AttackDef {
+    pub id: String,
+    pub name: String,
+    pub event: EventType,
+    pub range: RangeType,
+    #[serde(default)]
+    pub invokes_reaction: bool,
+    #[serde(default)]
+    pub policy: TargetingPolicy,
+    /// Catch the attacker too if it stands in the area, e.g. for blasts set off close by
+    #[serde(default)]
+    pub hits_self: bool,
+    /// Drop targets in walls or out of sight of the attacker
+    #[serde(default)]
+    pub clip: bool,
+    /// Scheduler ticks between declaring the attack and it landing
+    #[serde(default)]
+    pub windup: i32,
+    /// Energy the attacker spends on it, so quick jabs come around again sooner than heavy swings
+    #[serde(default = "default_attack_cost")]
+    pub cost: i32,
+    pub particle: Option<ParticleDef>,
+    /// Ranged attacks fly to their target and land on whatever gets in the way first
+    #[serde(default)]
+    pub projectile: Option<ProjectileDef>,
+}
+
+/// A kind of monster, with the behavior it uses to pick its actions
+#[derive(Deserialize, Clone)]
+pub struct MonsterDef {
+    pub id: String,
+    pub name: String,
+    pub glyph: char,
+    pub color: String,
+    /// `color` parsed, filled in when the raws are loaded
+    #[serde(skip)]
+    pub rgb: RGB,
+    pub health: i32,
+    /// How quickly its turns come around, see Schedulable::delta
+    pub speed: i32,
+    pub vision: i32,
+    pub bump_attack: String,
+    pub faction: String,
+    pub behavior: Behavior,
+    /// Abilities the behavior can pick from with UseAbilities
+    #[serde(default)]
+    pub moveset: Vec<Ability>,
+    /// Shallowest level it shows up on
+    #[serde(default)]
+    pub min_depth: i32,
+    /// How many of its kind go into the spawn rotation, relative to the other monsters
+    #[serde(default = "default_spawn_weight")]
+    pub spawn_weight: i32,
+    /// Turns spent searching for a target it has lost sight of
+    #[serde(default = "default_patience")]
+    pub patience: i32,
+}
+
+/// Something that can be found lying around and carried, used up by making its attack
+#[derive(Deserialize, Clone)]
+pub struct ItemDef {
+    pub id: String,
+    pub name: String,
+    pub glyph: char,
+    pub color: String,
+    /// `color` parsed, filled in when the raws are loaded
+    #[serde(skip)]
+    pub rgb: RGB,
+    pub attack: String,
+    /// How far away it can be thrown, or 0 for items used on whoever carries them
+    #[serde(default)]
+    pub reach: i32,
+    #[serde(default)]
+    pub min_depth: i32,
+    #[serde(default = "default_spawn_weight")]
+    pub spawn_weight: i32,
+}
+
+impl ItemDef {
+    /// Using an item is aimed the same way as an ability without a cooldown
+    pub fn as_ability(&self) -> Ability {
+        Ability {
+            attack: self.attack.clone(),
+            reach: self.reach,
+            cooldown: 0,
+            remaining: 0,
+        }
+    }
+}
+
+fn default_spawn_weight() -> i32 {
+    1
+}
+
+fn default_patience() -> i32 {
+    10
+}
+
+fn default_attack_cost() -> i32 {
+    crate::ATTACK_COST
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ParticleDef {
+    pub glyph: char,
+    pub color: String,
+    /// `color` parsed, filled in when the raws are loaded and kept in saves along with the event
+    #[serde(default)]
+    pub rgb: RGB,
+    pub lifetime: f32,
+}
+
+impl ParticleDef {
+    pub fn request_at(&self, position: Point) -> ParticleRequest {
+        ParticleRequest {
+            position,
+            color: self.rgb,
+            symbol: rltk::to_cp437(self.glyph),
+            lifetime: self.lifetime,
+            delay: 0.0,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ProjectileDef {
+    pub glyph: char,
+    pub color: String,
+    /// `color` parsed, filled in when the raws are loaded
+    #[serde(skip)]
+    pub rgb: RGB,
+    /// Milliseconds the projectile takes to cross each tile
+    pub tile_time: f32,
+}
+
+impl ProjectileDef {
+    /// One particle per tile of the path, each showing up as the previous one fades
+    pub fn requests_along(&self, path: &[Point]) -> Vec<ParticleRequest> {
+        path.iter()
+            .enumerate()
+            .map(|(i, position)| ParticleRequest {
+                position: *position,
+                color: self.rgb,
+                symbol: rltk::to_cp437(self.glyph),
+                lifetime: self.tile_time,
+                delay: i as f32 * self.tile_time,
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum RawsError {
+    Io {
+        path: String,
+        err: std::io::Error,
+    },
+    Parse {
+        path: String,
+        err: serde_json::Error,
+    },
+    DuplicateAttack(String),
+    DuplicateMonster(String),
+    DuplicateItem(String),
+    InvalidColor {
+        id: String,
+        color: String,
+    },
+    UnknownAttack(String),
+    UnknownMonster(String),
+    UnknownItem(String),
+    UnknownFaction(String),
+}
+
+impl fmt::Display for RawsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RawsError::Io { path, err } => write!(f, "failed to read raws file {}: {}", path, err),
+            RawsError::Parse { path, err } => {
+                write!(f, "failed to parse raws file {}: {}", path, err)
+            }
+            RawsError::DuplicateAttack(id) => write!(f, "attack '{}' is defined twice", id),
+            RawsError::DuplicateMonster(id) => write!(f, "monster '{}' is defined twice", id),
+            RawsError::DuplicateItem(id) => write!(f, "item '{}' is defined twice", id),
+            RawsError::InvalidColor { id, color } => {
+                write!(f, "'{}' has an invalid color '{}'", id, color)
+            }
+            RawsError::UnknownAttack(id) => write!(f, "no attack with id '{}' is defined", id),
+            RawsError::UnknownMonster(id) => write!(f, "no monster with id '{}' is defined", id),
+            RawsError::UnknownItem(id) => write!(f, "no item with id '{}' is defined", id),
+            RawsError::UnknownFaction(name) => write!(f, "no faction named '{}' is defined", name),
+        }
+    }
+}
+
+impl std::error::Error for RawsError {}
+
+/// Run `f` with the raws taken out of the world, since spawning needs the world mutably
+/// They are put back afterwards
+pub fn with_raws<R>(ecs: &mut World, f: impl FnOnce(&RawMaster, &mut World) -> R) -> R {
+    let raws = ecs
+        .remove::<RawMaster>()
+        .expect("Raws are not loaded into the world");
+    let result = f(&raws, ecs);
+    ecs.insert(raws);
+    result
+}
+
+/// Colors are parsed once while loading, so a typo stops the load rather than showing up later
+fn parse_color(id: &str, color: &str) -> Result<RGB, RawsError> {
+    RGB::from_hex(color).map_err(|_| RawsError::InvalidColor {
+        id: id.to_string(),
+        color: color.to_string(),
+    })
+}
+
+/// Attack, monster, item and faction definitions loaded at startup, looked up by their string ID,
+/// along with the moveset the player starts with
+pub struct RawMaster {
+    attacks: Vec<AttackDef>,
+    attack_index: HashMap<String, usize>,
+    monsters: Vec<MonsterDef>,
+    monster_index: HashMap<String, usize>,
+    items: Vec<ItemDef>,
+    item_index: HashMap<String, usize>,
+    /// Every faction's enemies, filled in both ways round, so lookups don't need to allocate
+    hostilities: HashMap<String, HashSet<String>>,
+    player_moveset: Vec<Ability>,
+}
+
+impl RawMaster {
+    pub fn load(path: &str) -> Result<RawMaster, RawsError> {
+        let contents = std::fs::read_to_string(path).map_err(|err| RawsError::Io {
+            path: path.to_string(),
+            err,
+        })?;
+        RawMaster::parse(path, &contents)
+    }
+
+    /// Build the raws from JSON that is already in memory, with `path` naming it in errors
+    pub fn parse(path: &str, contents: &str) -> Result<RawMaster, RawsError> {
+        let mut raws: RawFile = serde_json::from_str(contents).map_err(|err| RawsError::Parse {
+            path: path.to_string(),
+            err,
+        })?;
+
+        let mut attack_index = HashMap::new();
+        for (i, attack) in raws.attacks.iter_mut().enumerate() {
+            if let Some(particle) = &mut attack.particle {
+                particle.rgb = parse_color(&attack.id, &particle.color)?;
+            }
+            if let Some(projectile) = &mut attack.projectile {
+                projectile.rgb = parse_color(&attack.id, &projectile.color)?;
+            }
+
+            if attack_index.insert(attack.id.clone(), i).is_some() {
+                return Err(RawsError::DuplicateAttack(attack.id.clone()));
+            }
+        }
+
+        let faction_names: HashSet<&str> = raws.factions.iter().map(|f| f.name.as_str()).collect();
+        let mut hostilities: HashMap<String, HashSet<String>> = HashMap::new();
+        for faction in raws.factions.iter() {
+            for other in faction.hostile_to.iter() {
+                if !faction_names.contains(other.as_str()) {
+                    return Err(RawsError::UnknownFaction(other.clone()));
+                }
+
+                hostilities
+                    .entry(faction.name.clone())
+                    .or_default()
+                    .insert(other.clone());
+                hostilities
+                    .entry(other.clone())
+                    .or_default()
+                    .insert(faction.name.clone());
+            }
+        }
+
+        let mut monster_index = HashMap::new();
+        for (i, monster) in raws.monsters.iter_mut().enumerate() {
+            monster.rgb = parse_color(&monster.id, &monster.color)?;
+
+            // catch typos in attack names now rather than when the monster first swings
+            let attacks = std::iter::once(monster.bump_attack.as_str())
+                .chain(monster.behavior.attacks())
+                .chain(
+                    monster
+                        .moveset
+                        .iter()
+                        .map(|ability| ability.attack.as_str()),
+                );
+            for attack in attacks {
+                if !attack_index.contains_key(attack) {
+                    return Err(RawsError::UnknownAttack(attack.to_string()));
+                }
+            }
+
+            if !faction_names.contains(monster.faction.as_str()) {
+                return Err(RawsError::UnknownFaction(monster.faction.clone()));
+            }
+
+            if monster_index.insert(monster.id.clone(), i).is_some() {
+                return Err(RawsError::DuplicateMonster(monster.id.clone()));
+            }
+        }
+
+        let mut item_index = HashMap::new();
+        for (i, item) in raws.items.iter_mut().enumerate() {
+            item.rgb = parse_color(&item.id, &item.color)?;
+
+            if !attack_index.contains_key(&item.attack) {
+                return Err(RawsError::UnknownAttack(item.attack.clone()));
+            }
+
+            if item_index.insert(item.id.clone(), i).is_some() {
+                return Err(RawsError::DuplicateItem(item.id.clone()));
+            }
+        }
+
+        for ability in raws.player_moveset.iter() {
+            if !attack_index.contains_key(&ability.attack) {
+                return Err(RawsError::UnknownAttack(ability.attack.clone()));
+            }
+        }
+
+        // monsters can only be summoned once they are all known
+        for attack in raws.attacks.iter() {
+            for monster in attack.event.summons() {
+                if !monster_index.contains_key(monster) {
+                    return Err(RawsError::UnknownMonster(monster.to_string()));
+                }
+            }
+        }
+
+        Ok(RawMaster {
+            attacks: raws.attacks,
+            attack_index,
+            monsters: raws.monsters,
+            monster_index,
+            items: raws.items,
+            item_index,
+            hostilities,
+            player_moveset: raws.player_moveset,
+        })
+    }
+
+    pub fn get_attack(&self, id: &str) -> Option<&AttackDef> {
+        self.attack_index.get(id).map(|i| &self.attacks[*i])
+    }
+
+    /// The energy an attack costs, or a standard attack's cost if it doesn't exist
+    pub fn attack_cost(&self, id: &str) -> i32 {
+        self.get_attack(id)
+            .map_or(crate::ATTACK_COST, |attack| attack.cost)
+    }
+
+    /// Check that an attack exists, returning an owned ID that can be stored in a component
+    pub fn attack_id(&self, id: &str) -> Result<String, RawsError> {
+        if self.attack_index.contains_key(id) {
+            Ok(id.to_string())
+        } else {
+            Err(RawsError::UnknownAttack(id.to_string()))
+        }
+    }
+
+    pub fn get_monster(&self, id: &str) -> Result<&MonsterDef, RawsError> {
+        self.monster_index
+            .get(id)
+            .map(|i| &self.monsters[*i])
+            .ok_or_else(|| RawsError::UnknownMonster(id.to_string()))
+    }
+
+    pub fn get_item(&self, id: &str) -> Result<&ItemDef, RawsError> {
+        self.item_index
+            .get(id)
+            .map(|i| &self.items[*i])
+            .ok_or_else(|| RawsError::UnknownItem(id.to_string()))
+    }
+
+    /// The abilities the player starts out with
+    pub fn player_moveset(&self) -> &[Ability] {
+        &self.player_moveset
+    }
+
+    pub fn is_hostile(&self, faction: &str, other: &str) -> bool {
+        self.hostilities
+            .get(faction)
+            .is_some_and(|enemies| enemies.contains(other))
+    }
+
+    /// Monsters that can appear at a depth, each repeated by its spawn weight
+    pub fn spawn_rotation(&self, depth: i32) -> Vec<&MonsterDef> {
+        self.monsters
+            .iter()
+            .filter(|monster| monster.min_depth <= depth)
+            .flat_map(|monster| std::iter::repeat_n(monster, monster.spawn_weight.max(0) as usize))
+            .collect()
+    }
+
+    /// Items that can appear at a depth, each repeated by its spawn weight
+    pub fn item_rotation(&self, depth: i32) -> Vec<&ItemDef> {
+        self.items
+            .iter()
+            .filter(|item| item.min_depth <= depth)
+            .flat_map(|item| std::iter::repeat_n(item, item.spawn_weight.max(0) as usize))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    /// The smallest raws that load: one attack, one monster using it and one item
+    fn minimal() -> Value {
+        json!({
+            "attacks": [{
+                "id": "punch",
+                "name": "Punch",
+                "event": { "Damage": { "amount": 1 } },
+                "range": "Single",
+                "particle": { "glyph": "*", "color": "#FF0000", "lifetime": 100.0 }
+            }],
+            "factions": [{ "name": "beasts" }],
+            "monsters": [{
+                "id": "rat",
+                "name": "Rat",
+                "glyph": "r",
+                "color": "#808080",
+                "health": 1,
+                "speed": 4,
+                "vision": 4,
+                "bump_attack": "punch",
+                "faction": "beasts",
+                "behavior": "Chase"
+            }],
+            "items": [{
+                "id": "rock",
+                "name": "Rock",
+                "glyph": "*",
+                "color": "#A0A0A0",
+                "attack": "punch",
+                "reach": 4
+            }],
+            "player_moveset": [{ "attack": "punch", "reach": 1, "cooldown": 0 }]
+        })
+    }
+
+    fn load(raws: Value) -> Result<RawMaster, RawsError> {
+        RawMaster::parse("test", &raws.to_string())
+    }
+
+    #[test]
+    fn minimal_raws_load_with_parsed_colors() {
+        let raws = load(minimal()).expect("Failed to load minimal raws");
+
+        let monster = raws.get_monster("rat").expect("Rat is missing");
+        assert_eq!(monster.rgb, RGB::from_u8(0x80, 0x80, 0x80));
+        let particle = raws
+            .get_attack("punch")
+            .and_then(|attack| attack.particle.as_ref())
+            .expect("Punch has no particle");
+        assert_eq!(particle.rgb, RGB::from_u8(0xFF, 0, 0));
+    }
+
+    #[test]
+    fn unknown_attack_is_refused() {
+        let mut raws = minimal();
+        raws["monsters"][0]["bump_attack"] = json!("kick");
+
+        assert!(matches!(load(raws), Err(RawsError::UnknownAttack(id)) if id == "kick"));
+    }
+
+    #[test]
+    fn bad_color_is_refused() {
+        let mut raws = minimal();
+        raws["items"][0]["color"] = json!("grey");
+
+        assert!(matches!(
+            load(raws),
+            Err(RawsError::InvalidColor { id, color }) if id == "rock" && color == "grey"
+        ));
+    }
+
+    #[test]
+    fn missing_moveset_attack_is_refused() {
+        let mut raws = minimal();
+        raws["monsters"][0]["moveset"] = json!([{ "attack": "bite", "reach": 1, "cooldown": 2 }]);
+        assert!(matches!(load(raws), Err(RawsError::UnknownAttack(id)) if id == "bite"));
+
+        let mut raws = minimal();
+        raws["player_moveset"][0]["attack"] = json!("stab");
+        assert!(matches!(load(raws), Err(RawsError::UnknownAttack(id)) if id == "stab"));
+    }
+}
@@ -0,0 +1,51 @@
+use rand::distributions::{uniform::SampleUniform, Distribution, Standard};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use serde::{Deserialize, Serialize};
+
+/// The random number generator for the run, stored as a World resource
+/// All rolls should go through this so that the seed reproduces the whole run
+/// The generator state is saved as it is, so a loaded game rolls exactly what the saved one would
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GameRng {
+    seed: u64,
+    rng: XorShiftRng,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> GameRng {
+        GameRng {
+            seed,
+            rng: XorShiftRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Start a run with a seed picked at random
+    pub fn from_entropy() -> GameRng {
+        GameRng::new(rand::random())
+    }
+
+    /// The seed the run started with
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// A random value of whatever type is asked for
+    pub fn rand<T>(&mut self) -> T
+    where
+        Standard: Distribution<T>,
+    {
+        self.rng.gen::<T>()
+    }
+
+    /// A random value from `min` up to but not including `max`
+    pub fn range<T: SampleUniform>(&mut self, min: T, max: T) -> T {
+        self.rng.gen_range(min, max)
+    }
+
+    /// Put `items` in a random order
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        items.shuffle(&mut self.rng);
+    }
+}
@@ -0,0 +1,297 @@
+use super::dungeon::Dungeon;
+use super::raws::RawMaster;
+use super::rng::GameRng;
+use super::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Bump this whenever the layout of SaveGame or anything saved inside it changes
+pub const SAVE_VERSION: u32 = 14;
+
+pub const SAVE_PATH: &str = "savegame.json";
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io { path: String, err: std::io::Error },
+    Json(serde_json::Error),
+    VersionMismatch { found: u32, expected: u32 },
+    MissingPlayer,
+    MissingRaws,
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::Io { path, err } => {
+                write!(f, "failed to access save file {}: {}", path, err)
+            }
+            SaveError::Json(err) => write!(f, "save data is malformed: {}", err),
+            SaveError::VersionMismatch { found, expected } => write!(
+                f,
+                "save is from version {}, but this build reads version {}",
+                found, expected
+            ),
+            SaveError::MissingPlayer => write!(f, "save does not contain the player"),
+            SaveError::MissingRaws => write!(f, "raws must be loaded before a save"),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+// every component that outlives a single frame, i.e. everything except particles and cards
+macro_rules! saved_components {
+    ($($component:ident),* $(,)?) => {
+        #[derive(Serialize, Deserialize, Clone)]
+        enum SavedComponent {
+            $($component($component),)*
+        }
+
+        fn save_components(ecs: &World, ent: Entity) -> Vec<SavedComponent> {
+            let mut saved = Vec::new();
+            $(
+                if let Some(component) = ecs.read_storage::<$component>().get(ent) {
+                    saved.push(SavedComponent::$component(component.clone()));
+                }
+            )*
+            saved
+        }
+
+        fn load_component(ecs: &mut World, ent: Entity, component: SavedComponent) {
+            match component {
+                $(
+                    SavedComponent::$component(component) => {
+                        ecs.write_storage::<$component>()
+                            .insert(ent, component)
+                            .expect("Failed to insert loaded component");
+                    }
+                )*
+            }
+        }
+    };
+}
+
+saved_components!(
+    Position,
+    Renderable,
+    Player,
+    Name,
+    Viewshed,
+    CanActFlag,
+    CanReactFlag,
+    Schedulable,
+    BlocksTile,
+    Health,
+    DeathTrigger,
+    BumpAttack,
+    AttackIntent,
+    Telegraph,
+    MoveIntent,
+    Moveset,
+    Ai,
+    Faction,
+    Item,
+    Inventory,
+    ReactIntent,
+    Guarding,
+    StatusEffects,
+);
+
+/// The components of a single entity, which can be stored and later turned back into an entity
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SavedEntity(Vec<SavedComponent>);
+
+pub fn save_entity(ecs: &World, ent: Entity) -> SavedEntity {
+    SavedEntity(save_components(ecs, ent))
+}
+
+pub fn load_entity(ecs: &mut World, saved: SavedEntity) -> Entity {
+    let ent = ecs.create_entity().build();
+    for component in saved.0 {
+        load_component(ecs, ent, component);
+    }
+    ent
+}
+
+/// Read first, so that an old save fails with a version error instead of a parse error
+#[derive(Deserialize)]
+struct SaveHeader {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SaveGame {
+    version: u32,
+    tick: i32,
+    rng: GameRng,
+    run_state: RunState,
+    map: Map,
+    log: Vec<String>,
+    player: usize,
+    entities: Vec<SavedEntity>,
+    event_stack: SavedEventStack,
+    dungeon: Dungeon,
+}
+
+/// Serialize a world along with its turn counter
+/// Entities are stored by index, so references between them survive the round trip
+pub fn save_to_string(ecs: &World, tick: i32) -> Result<String, SaveError> {
+    let entities = ecs.entities();
+    let particles = ecs.read_storage::<ParticleLifetime>();
+    let cards = ecs.read_storage::<CardLifetime>();
+
+    let mut index_of = HashMap::new();
+    let mut saved_entities = Vec::new();
+    for (ent, _, _) in (&entities, !&particles, !&cards).join() {
+        index_of.insert(ent, saved_entities.len());
+        saved_entities.push(save_entity(ecs, ent));
+    }
+
+    let player = *index_of
+        .get(&*ecs.fetch::<Entity>())
+        .ok_or(SaveError::MissingPlayer)?;
+
+    let save = SaveGame {
+        version: SAVE_VERSION,
+        tick,
+        rng: (*ecs.fetch::<GameRng>()).clone(),
+        run_state: *ecs.fetch::<RunState>(),
+        map: (*ecs.fetch::<Map>()).clone(),
+        log: ecs.fetch::<gamelog::GameLog>().entries.clone(),
+        player,
+        entities: saved_entities,
+        event_stack: ecs
+            .fetch::<EventStack>()
+            .to_saved(|ent| index_of.get(&ent).copied()),
+        dungeon: (*ecs.fetch::<Dungeon>()).clone(),
+    };
+
+    serde_json::to_string(&save).map_err(SaveError::Json)
+}
+
+/// Rebuild a world from a save, returning it along with its turn counter
+/// Raws are not part of the save, so they need to be inserted before the systems run
+pub fn load_from_str(data: &str) -> Result<(World, i32), SaveError> {
+    let header: SaveHeader = serde_json::from_str(data).map_err(SaveError::Json)?;
+    if header.version != SAVE_VERSION {
+        return Err(SaveError::VersionMismatch {
+            found: header.version,
+            expected: SAVE_VERSION,
+        });
+    }
+
+    let save: SaveGame = serde_json::from_str(data).map_err(SaveError::Json)?;
+
+    let mut ecs = World::new();
+    spawner::register_components(&mut ecs);
+
+    let loaded: Vec<Entity> = save
+        .entities
+        .into_iter()
+        .map(|saved| load_entity(&mut ecs, saved))
+        .collect();
+
+    let player = *loaded.get(save.player).ok_or(SaveError::MissingPlayer)?;
+    let stack = EventStack::from_saved(save.event_stack, |i| loaded.get(i).copied());
+
+    ecs.insert(player);
+    ecs.insert(save.run_state);
+    ecs.insert(save.map);
+    ecs.insert(gamelog::GameLog { entries: save.log });
+    ecs.insert(stack);
+    ecs.insert(save.dungeon);
+    ecs.insert(save.rng);
+
+    Ok((ecs, save.tick))
+}
+
+pub fn save_game(gs: &State, path: &str) -> Result<(), SaveError> {
+    let data = save_to_string(&gs.ecs, gs.tick)?;
+    std::fs::write(path, data).map_err(|err| SaveError::Io {
+        path: path.to_string(),
+        err,
+    })
+}
+
+/// Replace the current game with the one saved at `path`, keeping the loaded raws
+/// On failure the current game is left untouched
+pub fn load_game(gs: &mut State, path: &str) -> Result<(), SaveError> {
+    let data = std::fs::read_to_string(path).map_err(|err| SaveError::Io {
+        path: path.to_string(),
+        err,
+    })?;
+
+    let (mut ecs, tick) = load_from_str(&data)?;
+    let raws = gs.ecs.remove::<RawMaster>().ok_or(SaveError::MissingRaws)?;
+    ecs.insert(raws);
+
+    gs.ecs = ecs;
+    gs.tick = tick;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::HeadlessRunner;
+    use rltk::Algorithm2D;
+
+    fn take_stairs_down(ecs: &mut World) {
+        let stairs = {
+            let map = ecs.fetch::<Map>();
+            let index = map
+                .tiles
+                .iter()
+                .position(|tile| *tile == TileType::DownStairs)
+                .expect("Level has no stairs down");
+            map.index_to_point2d(index)
+        };
+
+        let player = *ecs.fetch::<Entity>();
+        ecs.write_storage::<Position>()
+            .insert(
+                player,
+                Position {
+                    x: stairs.x,
+                    y: stairs.y,
+                },
+            )
+            .expect("Failed to move the player");
+
+        let changed = dungeon::change_level(ecs, 1).expect("Failed to change level");
+        assert!(changed);
+    }
+
+    /// A seeded run two levels down, with the levels above stored away, played for a while
+    fn played_world() -> (World, i32) {
+        let raws = RawMaster::load("raws/raws.json").expect("Failed to load raws");
+        let mut ecs =
+            spawner::build_world(raws, GameRng::new(7), 80, 50).expect("Failed to build world");
+        take_stairs_down(&mut ecs);
+        take_stairs_down(&mut ecs);
+
+        let mut runner = HeadlessRunner::new(ecs);
+        let mut commands = player::parse_script("wait pass")
+            .expect("Failed to parse script")
+            .into_iter()
+            .cycle();
+        for _ in 0..100 {
+            assert!(runner.step(&mut commands));
+        }
+
+        let tick = runner.tick();
+        (runner.into_world(), tick)
+    }
+
+    #[test]
+    fn save_load_save_is_byte_identical() {
+        let (ecs, tick) = played_world();
+        let first = save_to_string(&ecs, tick).expect("Failed to save");
+
+        let (loaded, loaded_tick) = load_from_str(&first).expect("Failed to load");
+        let second = save_to_string(&loaded, loaded_tick).expect("Failed to save again");
+
+        assert_eq!(first, second);
+    }
+}
@@ -0,0 +1,277 @@
+use super::raws::{RawMaster, RawsError};
+use super::rng::GameRng;
+use super::*;
+use rltk::{Point, RGB};
+
+/// Register every component and insert the resources that the systems expect
+pub fn register_components(ecs: &mut World) {
+    ecs.register::<Position>();
+    ecs.register::<Renderable>();
+    ecs.register::<Player>();
+    ecs.register::<Name>();
+    ecs.register::<Viewshed>();
+    ecs.register::<CanActFlag>();
+    ecs.register::<CanReactFlag>();
+    ecs.register::<Schedulable>();
+    ecs.register::<ParticleLifetime>();
+    ecs.register::<CardLifetime>();
+    ecs.register::<BlocksTile>();
+
+    ecs.register::<Health>();
+    ecs.register::<DeathTrigger>();
+    ecs.register::<BumpAttack>();
+    ecs.register::<AttackIntent>();
+    ecs.register::<Telegraph>();
+    ecs.register::<MoveIntent>();
+    ecs.register::<Moveset>();
+    ecs.register::<Ai>();
+    ecs.register::<Faction>();
+    ecs.register::<Item>();
+    ecs.register::<Inventory>();
+    ecs.register::<ReactIntent>();
+    ecs.register::<Guarding>();
+    ecs.register::<StatusEffects>();
+
+    ecs.insert(RunState::Running);
+    ecs.insert(ParticleBuilder::new());
+    ecs.insert(EventStack::default());
+}
+
+/// Build a new World with a freshly generated map and everything on it
+pub fn build_world(
+    raws: RawMaster,
+    mut rng: GameRng,
+    width: i32,
+    height: i32,
+) -> Result<World, RawsError> {
+    let mut ecs = World::new();
+    register_components(&mut ecs);
+
+    let built = map_builders::build_level(width, height, 0, &mut rng);
+    let player_pos = built.start;
+    let depth = built.map.depth;
+    ecs.insert(built.map);
+    populate_level(&mut ecs, &raws, &built.spawns, depth)?;
+    ecs.insert(dungeon::Dungeon::default());
+
+    let log = gamelog::GameLog {
+        entries: vec!["Hello world!".to_string()],
+    };
+    ecs.insert(log);
+
+    let player = player(&mut ecs, &raws, player_pos.x, player_pos.y)?;
+    ecs.insert(player);
+
+    explosive_barrel(&mut ecs, &raws, player_pos.x - 1, player_pos.y - 1)?;
+    if let Some(spot) = dungeon::free_tiles_near(&ecs, player_pos, 1).first() {
+        monster(&mut ecs, &raws, "hound", spot.x, spot.y)?;
+    }
+
+    // something to start the run with
+    let spots = dungeon::free_tiles_near(&ecs, player_pos, 2);
+    for (id, spot) in ["healing_potion", "bomb"].iter().zip(spots) {
+        item(&mut ecs, &raws, id, spot.x, spot.y)?;
+    }
+
+    ecs.insert(raws);
+    ecs.insert(rng);
+    Ok(ecs)
+}
+
+/// Put an enemy on each spawn point the map builder picked, with an item next to every other one
+/// Enemies take turns from the raws' spawn rotation for the depth, and gain a point of health per level
+/// Items work the same way, taking turns from the item rotation
+pub fn populate_level(
+    ecs: &mut World,
+    raws: &RawMaster,
+    spawns: &[Point],
+    depth: i32,
+) -> Result<(), RawsError> {
+    let rotation: Vec<String> = raws
+        .spawn_rotation(depth)
+        .iter()
+        .map(|monster| monster.id.clone())
+        .collect();
+    if rotation.is_empty() {
+        return Ok(());
+    }
+
+    for (i, spawn) in spawns.iter().enumerate() {
+        let (x, y) = spawn.to_tuple();
+        let enemy = monster(ecs, raws, &rotation[i % rotation.len()], x, y)?;
+
+        if let Some(health) = ecs.write_storage::<Health>().get_mut(enemy) {
+            health.max += depth;
+            health.current += depth;
+        }
+    }
+
+    let items: Vec<String> = raws
+        .item_rotation(depth)
+        .iter()
+        .map(|item| item.id.clone())
+        .collect();
+    if items.is_empty() {
+        return Ok(());
+    }
+
+    for (i, spawn) in spawns.iter().step_by(2).enumerate() {
+        if let Some(spot) = dungeon::free_tiles_near(ecs, *spawn, 1).first() {
+            item(ecs, raws, &items[i % items.len()], spot.x, spot.y)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The faction the player is on, which companions share
+pub const PLAYER_FACTION: &str = "player";
+
+pub fn player(ecs: &mut World, raws: &RawMaster, x: i32, y: i32) -> Result<Entity, RawsError> {
+    Ok(ecs
+        .create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            symbol: rltk::to_cp437('@'),
+            fg: RGB::named(rltk::YELLOW),
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Player)
+        .with(Name {
+            name: "Player".to_string(),
+        })
+        .with(Schedulable {
+            current: 0,
+            delta: 4,
+        })
+        .with(Viewshed {
+            visible: Vec::new(),
+            dirty: true,
+            range: 8,
+        })
+        .with(CanReactFlag)
+        .with(Faction {
+            name: PLAYER_FACTION.to_string(),
+        })
+        //.with(BlocksTile)
+        .with(Health {
+            current: 10,
+            max: 10,
+        })
+        .with(BumpAttack {
+            attack: raws.attack_id("punch")?,
+        })
+        .with(Moveset {
+            abilities: raws.player_moveset().to_vec(),
+        })
+        .with(Inventory {
+            capacity: 10,
+            items: Vec::new(),
+        })
+        .build())
+}
+
+/// Spawn a monster from its definition in the raws
+pub fn monster(
+    ecs: &mut World,
+    raws: &RawMaster,
+    id: &str,
+    x: i32,
+    y: i32,
+) -> Result<Entity, RawsError> {
+    let def = raws.get_monster(id)?;
+
+    Ok(ecs
+        .create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            symbol: rltk::to_cp437(def.glyph),
+            fg: def.rgb,
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Schedulable {
+            current: 0,
+            delta: def.speed,
+        })
+        .with(Viewshed {
+            visible: Vec::new(),
+            dirty: true,
+            range: def.vision,
+        })
+        .with(Name {
+            name: def.name.clone(),
+        })
+        .with(BlocksTile)
+        .with(Health {
+            current: def.health,
+            max: def.health,
+        })
+        .with(BumpAttack {
+            attack: raws.attack_id(&def.bump_attack)?,
+        })
+        .with(Faction {
+            name: def.faction.clone(),
+        })
+        .with(Ai {
+            behavior: def.behavior.clone(),
+            home: Point::new(x, y),
+            patrol_step: 0,
+            last_seen: None,
+            search_turns: 0,
+            patience: def.patience,
+        })
+        .with(Moveset {
+            abilities: def.moveset.clone(),
+        })
+        .build())
+}
+
+/// Spawn an item from its definition in the raws, lying on the map
+pub fn item(
+    ecs: &mut World,
+    raws: &RawMaster,
+    id: &str,
+    x: i32,
+    y: i32,
+) -> Result<Entity, RawsError> {
+    let def = raws.get_item(id)?;
+
+    Ok(ecs
+        .create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            symbol: rltk::to_cp437(def.glyph),
+            fg: def.rgb,
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Name {
+            name: def.name.clone(),
+        })
+        .with(Item { id: def.id.clone() })
+        .build())
+}
+
+pub fn explosive_barrel(
+    ecs: &mut World,
+    raws: &RawMaster,
+    x: i32,
+    y: i32,
+) -> Result<Entity, RawsError> {
+    Ok(ecs
+        .create_entity()
+        .with(Position { x, y })
+        .with(Renderable {
+            symbol: rltk::to_cp437('#'),
+            fg: RGB::named(rltk::YELLOW),
+            bg: RGB::named(rltk::BLACK),
+        })
+        .with(Name {
+            name: "Explosive Barrel".to_string(),
+        })
+        .with(BlocksTile)
+        .with(Health { current: 2, max: 2 })
+        .with(DeathTrigger {
+            attack: raws.attack_id("explosion")?,
+        })
+        .build())
+}
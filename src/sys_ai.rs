@@ -1,5 +1,10 @@
-use super::{AttackIntent, CanActFlag, Map, MoveIntent, Position, Viewshed};
-use rltk::Algorithm2D;
+use super::behavior::{AiAction, AiContext};
+use super::{
+    raws::RawMaster, rng::GameRng, Ai, AttackIntent, BumpAttack, CanActFlag, Faction, Health, Map,
+    MoveIntent, Moveset, Position, Schedulable, StatusEffects, Telegraph, Viewshed, MOVE_COST,
+    WAIT_COST,
+};
+use rltk::Point;
 use specs::prelude::*;
 
 pub struct AiSystem;
@@ -12,52 +17,177 @@ impl<'a> System<'a> for AiSystem {
         WriteStorage<'a, MoveIntent>,
         WriteStorage<'a, AttackIntent>,
         ReadStorage<'a, Viewshed>,
+        ReadStorage<'a, BumpAttack>,
+        ReadStorage<'a, Telegraph>,
         ReadExpect<'a, Map>,
         ReadExpect<'a, Entity>,
+        WriteStorage<'a, Schedulable>,
+        ReadExpect<'a, RawMaster>,
+        WriteStorage<'a, Ai>,
+        ReadStorage<'a, Health>,
+        WriteExpect<'a, GameRng>,
+        ReadStorage<'a, Faction>,
+        WriteStorage<'a, Moveset>,
+        ReadStorage<'a, StatusEffects>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (entities, mut can_act, positions, mut moves, mut attacks, viewsheds, map, player) =
-            data;
+        let (
+            entities,
+            mut can_act,
+            positions,
+            mut moves,
+            mut attacks,
+            viewsheds,
+            bump_attacks,
+            telegraphs,
+            map,
+            player,
+            mut schedulables,
+            raws,
+            mut ais,
+            healths,
+            mut rng,
+            factions,
+            mut movesets,
+            statuses,
+        ) = data;
         let mut turn_done = Vec::new();
-        let player_pos = positions.get(*player).unwrap();
+        let player_pos = positions.get(*player).unwrap().as_point();
+        let player_faction = factions.get(*player).map(|faction| faction.name.as_str());
 
-        for (ent, _turn, pos, viewshed) in (&entities, &can_act, &positions, &viewsheds).join() {
-            if ent == *player {
-                // player turn, handled in player.rs
+        // anything alive that has picked a side can be fought over
+        let combatants: Vec<(Entity, Point, &str)> = (&entities, &positions, &factions, &healths)
+            .join()
+            .filter(|(_, _, _, health)| health.current > 0)
+            .map(|(ent, pos, faction, _)| (ent, pos.as_point(), faction.name.as_str()))
+            .collect();
+
+        for (ent, _turn, pos, viewshed, bump, ai, health, moveset) in (
+            &entities,
+            &can_act,
+            &positions,
+            &viewsheds,
+            (&bump_attacks).maybe(),
+            &mut ais,
+            (&healths).maybe(),
+            (&mut movesets).maybe(),
+        )
+            .join()
+        {
+            if telegraphs.get(ent).is_some() {
+                // still winding up an attack, so the turn is spent waiting on it
+                turn_done.push((ent, WAIT_COST));
                 continue;
             }
 
-            if viewshed
-                .visible
-                .iter()
-                .any(|pos| pos.x == player_pos.x && pos.y == player_pos.y)
-            {
-                // if we can see the player move towards them
-                let curr_index = map.get_index(pos.x, pos.y);
-                let player_index = map.get_index(player_pos.x, player_pos.y);
-                let path = rltk::a_star_search(curr_index, player_index, &*map);
-                let next_pos = map.index_to_point2d(path.steps[1]);
-
-                if next_pos.x == player_pos.x && next_pos.y == player_pos.y {
-                    let attack = AttackIntent {
-                        loc: next_pos,
-                        range: crate::RangeType::Single,
-                    };
-                    attacks.insert(ent, attack).expect("sth");
-                } else {
-                    let movement = MoveIntent { loc: next_pos };
-                    moves.insert(ent, movement).expect("something");
+            let faction = factions.get(ent).map(|faction| faction.name.as_str());
+            let here = pos.as_point();
+
+            // go after the closest hostile thing in view, breaking ties by entity id so runs replay the same
+            let target = faction.and_then(|faction| {
+                combatants
+                    .iter()
+                    .filter(|(other, other_pos, other_faction)| {
+                        *other != ent
+                            && raws.is_hostile(faction, other_faction)
+                            && viewshed.visible.contains(other_pos)
+                    })
+                    .min_by_key(|(other, other_pos, _)| {
+                        let distance =
+                            rltk::DistanceAlg::PythagorasSquared.distance2d(here, *other_pos);
+                        (distance as i32, other.id())
+                    })
+                    .map(|(_, other_pos, _)| *other_pos)
+            });
+
+            // remember where the target was, and slowly give up on it once it is out of sight
+            let just_spotted = target.is_some() && ai.last_seen.is_none();
+            if target.is_some() {
+                ai.last_seen = target;
+                ai.search_turns = ai.patience;
+            } else if ai.last_seen.is_some() {
+                ai.search_turns -= 1;
+                if ai.search_turns <= 0 {
+                    ai.last_seen = None;
                 }
-            } else {
-                // else wait for now
             }
 
-            turn_done.push(ent);
+            let mut ctx = AiContext {
+                map: &map,
+                pos: here,
+                target,
+                last_seen: ai.last_seen,
+                just_spotted,
+                health_fraction: health.map_or(1.0, |health| {
+                    health.current as f32 / health.max.max(1) as f32
+                }),
+                bump_attack: bump.map(|bump| bump.attack.as_str()),
+                abilities: moveset
+                    .as_ref()
+                    .map_or(&[], |moveset| moveset.abilities.as_slice()),
+                home: ai.home,
+                leader: if faction.is_some() && faction == player_faction {
+                    Some(player_pos)
+                } else {
+                    None
+                },
+                patrol_step: &mut ai.patrol_step,
+                rng: &mut rng,
+            };
+
+            // there is no firing at what can't be seen, so such a shot is given up before it's paid
+            let can_aim = |attack: &str, loc: Point| {
+                raws.get_attack(attack).is_none_or(|attack_def| {
+                    attack_def.projectile.is_none() || viewshed.visible.contains(&loc)
+                })
+            };
+
+            let cost = match ai.behavior.decide(&mut ctx) {
+                Some(AiAction::Move(loc)) => {
+                    moves
+                        .insert(ent, MoveIntent { loc })
+                        .expect("Failed to insert MoveIntent");
+                    MOVE_COST
+                }
+                Some(AiAction::Attack { loc, attack }) if can_aim(&attack, loc) => {
+                    let cost = raws.attack_cost(&attack);
+                    attacks
+                        .insert(ent, AttackIntent { loc, attack })
+                        .expect("Failed to insert AttackIntent");
+                    cost
+                }
+                Some(AiAction::UseAbility { slot, loc })
+                    if moveset
+                        .as_ref()
+                        .is_some_and(|moveset| can_aim(&moveset.abilities[slot].attack, loc)) =>
+                {
+                    let ability = &mut moveset
+                        .expect("Chose an ability without a Moveset")
+                        .abilities[slot];
+                    ability.remaining = ability.cooldown;
+
+                    let cost = raws.attack_cost(&ability.attack);
+                    let attack = ability.attack.clone();
+                    attacks
+                        .insert(ent, AttackIntent { loc, attack })
+                        .expect("Failed to insert AttackIntent");
+                    cost
+                }
+                Some(AiAction::Wait)
+                | Some(AiAction::Attack { .. })
+                | Some(AiAction::UseAbility { .. })
+                | None => WAIT_COST,
+            };
+
+            turn_done.push((ent, cost));
         }
 
-        for done in turn_done.iter() {
+        for (done, cost) in turn_done.iter() {
             can_act.remove(*done);
+            if let Some(sched) = schedulables.get_mut(*done) {
+                sched.charge(*cost, statuses.get(*done));
+            }
         }
     }
 }
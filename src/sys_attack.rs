@@ -1,23 +1,139 @@
-use super::AttackIntent;
+use super::{
+    raws::{AttackDef, RawMaster},
+    AttackIntent, EventStack, Health, Map, ParticleBuilder, Position, Telegraph, Viewshed,
+};
+use rltk::Point;
 use specs::prelude::*;
 
 pub struct AttackSystem;
 
 impl<'a> System<'a> for AttackSystem {
-    type SystemData = (Entities<'a>, WriteStorage<'a, AttackIntent>);
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, AttackIntent>,
+        Write<'a, EventStack>,
+        ReadExpect<'a, RawMaster>,
+        ReadStorage<'a, Position>,
+        WriteStorage<'a, Telegraph>,
+        ReadExpect<'a, Map>,
+        ReadStorage<'a, Viewshed>,
+        ReadStorage<'a, Health>,
+        WriteExpect<'a, ParticleBuilder>,
+    );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (entities, mut attacks) = data;
-
-        for (_, attack) in (&entities, &attacks).join() {
-            crate::add_event(
-                &crate::EventType::Damage { amount: 1 },
-                &attack.range,
-                attack.loc,
-                true,
-            )
+        let (
+            entities,
+            mut attacks,
+            mut stack,
+            raws,
+            positions,
+            mut telegraphs,
+            map,
+            viewsheds,
+            healths,
+            mut builder,
+        ) = data;
+
+        for (ent, attack, pos) in (&entities, &attacks, &positions).join() {
+            let attack_def = match raws.get_attack(&attack.attack) {
+                None => continue,
+                Some(attack_def) => attack_def,
+            };
+
+            let origin = pos.as_point();
+
+            // there is no firing at what can't be seen, which the player's aiming and AiSystem
+            // both check before the attack is paid for
+            if attack_def.projectile.is_some()
+                && viewsheds
+                    .get(ent)
+                    .is_some_and(|viewshed| !viewshed.visible.contains(&attack.loc))
+            {
+                continue;
+            }
+
+            let blockers = crate::projectile_blockers(ent, &entities, &positions, &healths);
+            let (path, loc) = landing(attack_def, origin, attack.loc, &map, blockers);
+
+            // slow attacks only let fly once their windup is over, see TelegraphSystem
+            if let Some(projectile) = &attack_def.projectile {
+                if attack_def.windup <= 0 {
+                    for request in projectile.requests_along(&path) {
+                        builder.make_particle(request);
+                    }
+                }
+            }
+
+            if attack_def.windup <= 0 {
+                stack.add_attack(attack_def, loc, origin, Some(ent));
+                continue;
+            }
+
+            // slow attacks are announced now and pushed onto the stack once the windup is over
+            let mut tiles = crate::resolve_range_at(&attack_def.range, loc, origin);
+            if attack_def.clip {
+                tiles = crate::clip_to_map(&tiles, origin, &map);
+            }
+
+            let telegraph = Telegraph {
+                attack: attack.attack.clone(),
+                loc,
+                origin,
+                tiles,
+                remaining: attack_def.windup,
+            };
+            telegraphs
+                .insert(ent, telegraph)
+                .expect("Failed to insert Telegraph");
         }
 
         attacks.clear();
     }
 }
+
+/// Where an attack aimed at `loc` comes down, along with the path its projectile takes there
+/// Attacks without a projectile land right where they were aimed
+pub fn landing(
+    attack_def: &AttackDef,
+    origin: Point,
+    loc: Point,
+    map: &Map,
+    is_occupied: impl Fn(Point) -> bool,
+) -> (Vec<Point>, Point) {
+    if attack_def.projectile.is_none() {
+        return (Vec::new(), loc);
+    }
+
+    let path = crate::trace_projectile(origin, loc, map, is_occupied);
+    let impact = path.last().copied().unwrap_or(origin);
+    (path, impact)
+}
+
+/// The projectile path and the tiles hit if `attacker` were to aim an attack at `loc` right now
+pub fn preview_attack(
+    ecs: &World,
+    attacker: Entity,
+    attack_def: &AttackDef,
+    loc: Point,
+) -> (Vec<Point>, Vec<Point>) {
+    let entities = ecs.entities();
+    let positions = ecs.read_storage::<Position>();
+    let healths = ecs.read_storage::<Health>();
+    let map = ecs.fetch::<Map>();
+
+    let origin = match positions.get(attacker) {
+        None => return (Vec::new(), Vec::new()),
+        Some(pos) => pos.as_point(),
+    };
+
+    let blockers = crate::projectile_blockers(attacker, &entities, &positions, &healths);
+    let (path, impact) = landing(attack_def, origin, loc, &map, blockers);
+
+    let mut tiles = crate::resolve_range_at(&attack_def.range, impact, origin);
+    if attack_def.clip {
+        tiles = crate::clip_to_map(&tiles, origin, &map);
+    }
+
+    (path, tiles)
+}
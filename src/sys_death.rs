@@ -1,4 +1,4 @@
-use super::{DeathTrigger, Health, Position};
+use super::{raws::RawMaster, DeathTrigger, EventStack, Health, Position};
 use specs::prelude::*;
 
 pub struct DeathSystem;
@@ -10,18 +10,20 @@ impl<'a> System<'a> for DeathSystem {
         ReadStorage<'a, Position>,
         ReadStorage<'a, DeathTrigger>,
         ReadStorage<'a, Health>,
+        Write<'a, EventStack>,
+        ReadExpect<'a, RawMaster>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (entities, player, positions, death_triggers, healths) = data;
+        let (entities, player, positions, death_triggers, healths, mut stack, raws) = data;
         let mut dead = Vec::new();
 
         for (ent, pos, health, effect) in
             (&entities, &positions, &healths, (&death_triggers).maybe()).join()
         {
             if health.current <= 0 {
-                if let Some(effect) = effect {
-                    crate::add_event(&effect.event, &effect.range, pos.as_point(), true);
+                if let Some(attack_def) = effect.and_then(|e| raws.get_attack(&e.attack)) {
+                    stack.add_attack(attack_def, pos.as_point(), pos.as_point(), Some(ent));
                 }
 
                 if ent != *player {
@@ -1,4 +1,4 @@
-use super::{MoveIntent, Position, Viewshed};
+use super::{Map, MoveIntent, Position, Schedulable, StatusEffects, TileType, Viewshed};
 use specs::prelude::*;
 
 pub struct MovementSystem;
@@ -9,28 +9,62 @@ impl<'a> System<'a> for MovementSystem {
         WriteStorage<'a, Position>,
         WriteStorage<'a, MoveIntent>,
         WriteStorage<'a, Viewshed>,
+        WriteStorage<'a, Schedulable>,
+        WriteExpect<'a, Map>,
+        ReadStorage<'a, StatusEffects>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (entities, mut positions, mut movements, mut viewsheds) = data;
+        let (
+            entities,
+            mut positions,
+            mut movements,
+            mut viewsheds,
+            mut schedulables,
+            mut map,
+            statuses,
+        ) = data;
+        let mut opened_door = false;
 
-        for (_, pos, movement, viewshed) in (
+        for (ent, pos, movement, viewshed, sched) in (
             &entities,
             &mut positions,
             &movements,
             (&mut viewsheds).maybe(),
+            (&mut schedulables).maybe(),
         )
             .join()
         {
             let new_pos = movement.loc;
+            let index = map.get_index(new_pos.x, new_pos.y);
+
+            // walking into a closed door opens it, and that takes the whole move
+            if map.tiles[index] == TileType::DoorClosed {
+                map.tiles[index] = TileType::DoorOpen;
+                map.blocked_tiles[index] = false;
+                opened_door = true;
+                continue;
+            }
+
             pos.x = new_pos.x;
             pos.y = new_pos.y;
 
+            if let Some(sched) = sched {
+                sched.charge(map.tiles[index].move_cost(), statuses.get(ent));
+            }
+
             if let Some(viewshed) = viewshed {
                 viewshed.dirty = true;
             }
         }
 
+        // an open door lets everyone nearby see further, not just whoever opened it
+        if opened_door {
+            for viewshed in (&mut viewsheds).join() {
+                viewshed.dirty = true;
+            }
+        }
+
         movements.clear();
     }
 }
@@ -1,30 +1,36 @@
 use super::{CardLifetime, ParticleLifetime, Position, Renderable};
-use rltk::{FontCharType, Point, Rltk, RGB};
+use rltk::{FontCharType, Point, RGB};
+use serde::{Deserialize, Serialize};
 use specs::prelude::*;
 
-pub fn cleanup_particles(ecs: &mut World, ctx: &Rltk) {
-    let dead_particles = update_lifetimes(ecs, ctx);
+pub fn cleanup_particles(ecs: &mut World, frame_time_ms: f32) {
+    let dead_particles = update_lifetimes(ecs, frame_time_ms);
 
     for dead in dead_particles.iter() {
         ecs.delete_entity(*dead).expect("Failed to delete particle");
     }
 }
 
-fn update_lifetimes(ecs: &mut World, ctx: &Rltk) -> Vec<Entity> {
+fn update_lifetimes(ecs: &mut World, frame_time_ms: f32) -> Vec<Entity> {
     let mut dead_particles = Vec::new();
     let mut particles = ecs.write_storage::<ParticleLifetime>();
     let mut cards = ecs.write_storage::<CardLifetime>();
     let entities = ecs.entities();
 
-    for (ent, mut lifetime) in (&entities, &mut particles).join() {
-        lifetime.remaining -= ctx.frame_time_ms;
+    for (ent, lifetime) in (&entities, &mut particles).join() {
+        if lifetime.delay > 0.0 {
+            lifetime.delay -= frame_time_ms;
+            continue;
+        }
+
+        lifetime.remaining -= frame_time_ms;
         if lifetime.remaining < 0.0 {
             dead_particles.push(ent);
         }
     }
 
-    for (ent, mut lifetime) in (&entities, &mut cards).join() {
-        lifetime.remaining -= ctx.frame_time_ms;
+    for (ent, lifetime) in (&entities, &mut cards).join() {
+        lifetime.remaining -= frame_time_ms;
         if lifetime.remaining < 0.0 {
             dead_particles.push(ent);
         }
@@ -33,20 +39,26 @@ fn update_lifetimes(ecs: &mut World, ctx: &Rltk) -> Vec<Entity> {
     dead_particles
 }
 
-#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, Copy, Clone)]
 pub struct ParticleRequest {
     pub position: Point,
     pub color: RGB,
     pub symbol: FontCharType,
     pub lifetime: f32,
+    #[serde(default)]
+    pub delay: f32,
 }
 
+#[derive(Serialize, Deserialize, Clone)]
 pub struct CardRequest {
     pub name: String,
+    pub details: Vec<String>,
+    pub source: Option<String>,
     pub offset: i32,
     pub affected: std::sync::Arc<Vec<rltk::Point>>,
 }
 
+#[derive(Default)]
 pub struct ParticleBuilder {
     requests: Vec<ParticleRequest>,
     card_stack: Vec<CardRequest>,
@@ -54,10 +66,7 @@ pub struct ParticleBuilder {
 
 impl ParticleBuilder {
     pub fn new() -> ParticleBuilder {
-        ParticleBuilder {
-            requests: Vec::new(),
-            card_stack: Vec::new(),
-        }
+        ParticleBuilder::default()
     }
 
     pub fn make_particle(&mut self, request: ParticleRequest) {
@@ -111,6 +120,7 @@ impl<'a> System<'a> for ParticleSpawnSystem {
                 .insert(
                     particle,
                     ParticleLifetime {
+                        delay: request.delay,
                         base: request.lifetime,
                         remaining: request.lifetime,
                         should_fade: true,
@@ -0,0 +1,53 @@
+use super::{AttackIntent, BumpAttack, Guarding, MoveIntent, ReactIntent, ReactionType};
+use specs::prelude::*;
+
+pub struct ReactionSystem;
+
+impl<'a> System<'a> for ReactionSystem {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, ReactIntent>,
+        WriteStorage<'a, MoveIntent>,
+        WriteStorage<'a, AttackIntent>,
+        WriteStorage<'a, Guarding>,
+        ReadStorage<'a, BumpAttack>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, mut reactions, mut moves, mut attacks, mut guards, bump_attacks) = data;
+
+        for (ent, react, bump) in (&entities, &reactions, (&bump_attacks).maybe()).join() {
+            match react.reaction {
+                ReactionType::Dodge => {
+                    moves
+                        .insert(ent, MoveIntent { loc: react.loc })
+                        .expect("Failed to insert MoveIntent for dodge");
+                }
+                ReactionType::Counter => {
+                    // countering strikes back with whatever the entity would bump with
+                    if let Some(bump) = bump {
+                        let attack = AttackIntent {
+                            loc: react.loc,
+                            attack: bump.attack.clone(),
+                        };
+                        attacks
+                            .insert(ent, attack)
+                            .expect("Failed to insert AttackIntent for counter");
+                    }
+                }
+                ReactionType::Parry | ReactionType::Brace => {
+                    guards
+                        .insert(
+                            ent,
+                            Guarding {
+                                reaction: react.reaction,
+                            },
+                        )
+                        .expect("Failed to insert Guarding");
+                }
+            }
+        }
+
+        reactions.clear();
+    }
+}
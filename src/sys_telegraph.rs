@@ -0,0 +1,73 @@
+use super::{
+    raws::RawMaster, sys_attack, EventStack, Health, Map, ParticleBuilder, Position, RunState,
+    Telegraph,
+};
+use specs::prelude::*;
+
+pub struct TelegraphSystem;
+
+impl<'a> System<'a> for TelegraphSystem {
+    type SystemData = (
+        Entities<'a>,
+        WriteStorage<'a, Telegraph>,
+        Write<'a, EventStack>,
+        ReadExpect<'a, RawMaster>,
+        ReadExpect<'a, RunState>,
+        ReadExpect<'a, Map>,
+        WriteExpect<'a, ParticleBuilder>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, Health>,
+    );
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (
+            entities,
+            mut telegraphs,
+            mut stack,
+            raws,
+            game_state,
+            map,
+            mut builder,
+            positions,
+            healths,
+        ) = data;
+
+        // windups only count down on ticks where the scheduler advanced
+        if *game_state != RunState::Running {
+            return;
+        }
+
+        let mut landed = Vec::new();
+
+        for (ent, telegraph) in (&entities, &mut telegraphs).join() {
+            telegraph.remaining -= 1;
+            if telegraph.remaining > 0 {
+                continue;
+            }
+
+            if let Some(attack_def) = raws.get_attack(&telegraph.attack) {
+                // projectiles only fly now, so they hit whoever got in the way during the windup
+                let blockers = crate::projectile_blockers(ent, &entities, &positions, &healths);
+                let (path, loc) = sys_attack::landing(
+                    attack_def,
+                    telegraph.origin,
+                    telegraph.loc,
+                    &map,
+                    blockers,
+                );
+                if let Some(projectile) = &attack_def.projectile {
+                    for request in projectile.requests_along(&path) {
+                        builder.make_particle(request);
+                    }
+                }
+
+                stack.add_attack(attack_def, loc, telegraph.origin, Some(ent));
+            }
+            landed.push(ent);
+        }
+
+        for ent in landed {
+            telegraphs.remove(ent);
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use super::{CanActFlag, Position, RunState, Schedulable};
+use super::{
+    gamelog::GameLog, raws::RawMaster, Aim, CanActFlag, Health, Inventory, Moveset, Name, Position,
+    RunState, Schedulable, StatusEffect, StatusEffects, StatusType, REACTION_COST, WAIT_COST,
+};
 use specs::prelude::*;
 
 pub struct TurnSystem;
@@ -11,10 +14,27 @@ impl<'a> System<'a> for TurnSystem {
         WriteStorage<'a, Schedulable>,
         ReadStorage<'a, Position>,
         ReadExpect<'a, Entity>,
+        WriteStorage<'a, StatusEffects>,
+        WriteStorage<'a, Health>,
+        ReadStorage<'a, Name>,
+        WriteExpect<'a, GameLog>,
+        WriteStorage<'a, Moveset>,
     );
 
     fn run(&mut self, data: Self::SystemData) {
-        let (mut game_state, entities, mut can_act, mut schedulables, pos, player) = data;
+        let (
+            mut game_state,
+            entities,
+            mut can_act,
+            mut schedulables,
+            positions,
+            player,
+            mut statuses,
+            mut healths,
+            names,
+            mut log,
+            mut movesets,
+        ) = data;
         assert!(*game_state == RunState::Running);
 
         if can_act.get(*player).is_some() {
@@ -22,16 +42,444 @@ impl<'a> System<'a> for TurnSystem {
             return;
         }
 
-        for (ent, sched, _pos) in (&entities, &mut schedulables, &pos).join() {
+        let mut spreading = Vec::new();
+
+        for (ent, sched, pos, mut status) in (
+            &entities,
+            &mut schedulables,
+            &positions,
+            (&mut statuses).maybe(),
+        )
+            .join()
+        {
             sched.current -= sched.delta;
+
+            if let Some(status) = &mut status {
+                for effect in status.effects.iter_mut() {
+                    effect.duration -= 1;
+                }
+                status.effects.retain(|effect| effect.duration > 0);
+            }
+
             if sched.current > 0 {
                 continue;
             }
 
-            sched.current += sched.base;
+            // cooldowns count the actor's own turns, even ones lost to a stun
+            if let Some(moveset) = movesets.get_mut(ent) {
+                for ability in moveset.abilities.iter_mut() {
+                    ability.remaining = i32::max(ability.remaining - 1, 0);
+                }
+            }
+
+            let mut stunned = false;
+
+            for effect in status.iter().flat_map(|status| status.effects.iter()) {
+                match effect.status {
+                    StatusType::Poison { damage } | StatusType::Burn { damage } => {
+                        if let Some(health) = healths.get_mut(ent) {
+                            health.current -= damage;
+                        }
+
+                        if let Some(name) = names.get(ent) {
+                            log.entries.push(format!(
+                                "{} takes {} {} damage",
+                                name.name,
+                                damage,
+                                effect.status.name().to_lowercase()
+                            ));
+                        }
+                    }
+                    StatusType::Stun => stunned = true,
+                    // slows are paid along with each action, see Schedulable::charge
+                    StatusType::Slow { .. } => {}
+                }
+
+                // fires catch on neighbours, burning half as long so they die out eventually
+                if let StatusType::Burn { .. } = effect.status {
+                    if effect.duration > 1 {
+                        spreading.push((
+                            ent,
+                            pos.as_point(),
+                            StatusEffect {
+                                status: effect.status,
+                                duration: effect.duration / 2,
+                            },
+                        ));
+                    }
+                }
+            }
+
+            // stunned actors lose this turn
+            if stunned {
+                sched.charge(WAIT_COST, status.as_deref());
+                continue;
+            }
+
             can_act
                 .insert(ent, CanActFlag { is_reaction: false })
                 .expect("Failed to insert CanActFlag");
         }
+
+        // only onto other actors, as statuses never tick on anything without a schedule
+        for (source, origin, burn) in spreading {
+            for (ent, pos, health, _) in (&entities, &positions, &healths, &schedulables).join() {
+                let distance = rltk::DistanceAlg::Chebyshev.distance2d(origin, pos.as_point());
+                if ent == source || health.current <= 0 || distance > 1.0 {
+                    continue;
+                }
+
+                let effects = &mut statuses
+                    .entry(ent)
+                    .expect("Failed to get StatusEffects entry")
+                    .or_insert_with(StatusEffects::default)
+                    .effects;
+                if effects
+                    .iter()
+                    .any(|effect| matches!(effect.status, StatusType::Burn { .. }))
+                {
+                    continue;
+                }
+
+                effects.push(burn.clone());
+                if let Some(name) = names.get(ent) {
+                    log.entries.push(format!("{} catches fire", name.name));
+                }
+            }
+        }
+    }
+}
+
+/// An actor's schedule as predict_turn_order runs it forward
+struct Timer {
+    ent: Entity,
+    current: i32,
+    delta: i32,
+    statuses: StatusEffects,
+}
+
+impl Timer {
+    /// Take one tick off the schedule the way TurnSystem does, returning whether a turn comes up
+    /// Turns lost to a stun are charged like waiting, but don't count
+    fn tick(&mut self) -> bool {
+        self.current -= self.delta;
+        for effect in self.statuses.effects.iter_mut() {
+            effect.duration -= 1;
+        }
+        self.statuses.effects.retain(|effect| effect.duration > 0);
+
+        if self.current > 0 {
+            return false;
+        }
+
+        let stunned = self
+            .statuses
+            .effects
+            .iter()
+            .any(|effect| effect.status == StatusType::Stun);
+        self.charge(WAIT_COST);
+        !stunned
+    }
+
+    fn charge(&mut self, cost: i32) {
+        self.current += self.statuses.scale_cost(cost);
+    }
+}
+
+/// What the player's current turn is going to cost, going by what they are aiming
+/// Anyone else, including monsters winding up an attack, is assumed to wait
+fn pending_cost(ecs: &World, ent: Entity, flag: &CanActFlag) -> i32 {
+    if ent != *ecs.fetch::<Entity>() {
+        return WAIT_COST;
+    }
+    if flag.is_reaction {
+        return REACTION_COST;
+    }
+
+    let raws = match ecs.try_fetch::<RawMaster>() {
+        None => return WAIT_COST,
+        Some(raws) => raws,
+    };
+    let attack = match *ecs.fetch::<RunState>() {
+        RunState::Targeting {
+            aim: Aim::Ability(slot),
+            ..
+        } => ecs
+            .read_storage::<Moveset>()
+            .get(ent)
+            .and_then(|moveset| moveset.abilities.get(slot))
+            .map(|ability| ability.attack.clone()),
+        RunState::Targeting {
+            aim: Aim::Item(slot),
+            ..
+        } => ecs
+            .read_storage::<Inventory>()
+            .get(ent)
+            .and_then(|inventory| inventory.items.get(slot))
+            .and_then(|id| raws.get_item(id).ok())
+            .map(|item| item.attack.clone()),
+        _ => None,
+    };
+
+    attack.map_or(WAIT_COST, |attack| raws.attack_cost(&attack))
+}
+
+/// Guess who acts next by running the scheduler forward the same way TurnSystem does
+/// Statuses wear off as the ticks go by, slowing and stunning only while they last
+/// Whoever is up right now pays for what they are doing first, see pending_cost, and every turn
+/// after that is assumed to cost the same as waiting, so this is only a preview
+/// Only `actors` are considered, returning up to `count` of them with the ticks until each turn
+pub fn predict_turn_order(ecs: &World, actors: &[Entity], count: usize) -> Vec<(Entity, i32)> {
+    let schedulables = ecs.read_storage::<Schedulable>();
+    let statuses = ecs.read_storage::<StatusEffects>();
+    let can_act = ecs.read_storage::<CanActFlag>();
+
+    let mut timers: Vec<Timer> = actors
+        .iter()
+        .filter_map(|ent| {
+            schedulables
+                .get(*ent)
+                .filter(|sched| sched.delta > 0)
+                .map(|sched| Timer {
+                    ent: *ent,
+                    current: sched.current,
+                    delta: sched.delta,
+                    statuses: statuses.get(*ent).cloned().unwrap_or_default(),
+                })
+        })
+        .collect();
+
+    // ties go in entity order, the same order TurnSystem hands out turns in
+    timers.sort_by_key(|timer| timer.ent.id());
+
+    let mut order = Vec::new();
+    for timer in timers.iter_mut() {
+        if let Some(flag) = can_act.get(timer.ent) {
+            order.push((timer.ent, 0));
+            timer.charge(pending_cost(ecs, timer.ent, flag));
+        }
+    }
+
+    let mut elapsed = 0;
+    while order.len() < count && !timers.is_empty() {
+        elapsed += 1;
+        for timer in timers.iter_mut() {
+            if timer.tick() {
+                order.push((timer.ent, elapsed));
+            }
+        }
+    }
+
+    order.truncate(count);
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{spawner, sys_movement::MovementSystem, Map, MoveIntent, TileType, MOVE_COST};
+
+    /// A world with an actor at (5, 5) whose turn comes up on the next tick, under `status`
+    fn world(status: StatusType) -> (World, Entity) {
+        let mut ecs = World::new();
+        spawner::register_components(&mut ecs);
+        ecs.insert(GameLog {
+            entries: Vec::new(),
+        });
+
+        // TurnSystem stops for the player, so there needs to be one, even if it never acts
+        let player = ecs.create_entity().build();
+        ecs.insert(player);
+
+        let actor = ecs
+            .create_entity()
+            .with(Position { x: 5, y: 5 })
+            .with(Schedulable {
+                current: 4,
+                delta: 4,
+            })
+            .with(Health {
+                current: 10,
+                max: 10,
+            })
+            .with(StatusEffects {
+                effects: vec![StatusEffect {
+                    status,
+                    duration: 20,
+                }],
+            })
+            .build();
+
+        (ecs, actor)
+    }
+
+    /// Someone standing around, whose turn is a long way off
+    fn bystander(ecs: &mut World, x: i32, y: i32) -> Entity {
+        let ent = barrel(ecs, x, y);
+        ecs.write_storage::<Schedulable>()
+            .insert(
+                ent,
+                Schedulable {
+                    current: 100,
+                    delta: 4,
+                },
+            )
+            .expect("Failed to insert Schedulable");
+        ent
+    }
+
+    /// Something with health that never gets a turn
+    fn barrel(ecs: &mut World, x: i32, y: i32) -> Entity {
+        ecs.create_entity()
+            .with(Position { x, y })
+            .with(Health {
+                current: 10,
+                max: 10,
+            })
+            .build()
+    }
+
+    fn health(ecs: &World, ent: Entity) -> i32 {
+        ecs.read_storage::<Health>()
+            .get(ent)
+            .map_or(0, |health| health.current)
+    }
+
+    fn energy(ecs: &World, ent: Entity) -> i32 {
+        ecs.read_storage::<Schedulable>()
+            .get(ent)
+            .map_or(0, |sched| sched.current)
+    }
+
+    fn can_act(ecs: &World, ent: Entity) -> bool {
+        ecs.read_storage::<CanActFlag>().get(ent).is_some()
+    }
+
+    #[test]
+    fn poison_damages_each_turn() {
+        let (ecs, actor) = world(StatusType::Poison { damage: 2 });
+        TurnSystem.run_now(&ecs);
+
+        assert_eq!(health(&ecs, actor), 8);
+        assert!(can_act(&ecs, actor));
+    }
+
+    #[test]
+    fn stun_skips_the_turn() {
+        let (ecs, actor) = world(StatusType::Stun);
+        TurnSystem.run_now(&ecs);
+
+        assert!(!can_act(&ecs, actor));
+        assert_eq!(energy(&ecs, actor), WAIT_COST);
+    }
+
+    #[test]
+    fn slow_scales_the_cost_of_actions() {
+        let (mut ecs, actor) = world(StatusType::Slow { amount: 50 });
+
+        // stepping onto rubble costs extra, and the slow makes that half again as much
+        let mut map = Map::new(12, 12, 0);
+        map.build_room(rltk::Rect::with_size(1, 1, 10, 10));
+        let rubble = map.get_index(6, 5);
+        map.tiles[rubble] = TileType::Rubble;
+        ecs.insert(map);
+
+        ecs.write_storage::<MoveIntent>()
+            .insert(
+                actor,
+                MoveIntent {
+                    loc: rltk::Point::new(6, 5),
+                },
+            )
+            .expect("Failed to insert MoveIntent");
+        MovementSystem.run_now(&ecs);
+
+        assert_eq!(
+            energy(&ecs, actor),
+            4 + TileType::Rubble.move_cost() * 3 / 2
+        );
+
+        let statuses = ecs.read_storage::<StatusEffects>();
+        let slowed = statuses.get(actor).expect("Actor lost its statuses");
+        assert_eq!(slowed.scale_cost(MOVE_COST), MOVE_COST * 3 / 2);
+    }
+
+    #[test]
+    fn burn_damages_and_spreads_to_neighbours() {
+        let (mut ecs, actor) = world(StatusType::Burn { damage: 1 });
+        let neighbour = bystander(&mut ecs, 6, 6);
+        let distant = bystander(&mut ecs, 8, 5);
+        let unscheduled = barrel(&mut ecs, 4, 4);
+        TurnSystem.run_now(&ecs);
+
+        assert_eq!(health(&ecs, actor), 9);
+
+        let statuses = ecs.read_storage::<StatusEffects>();
+        let caught = statuses
+            .get(neighbour)
+            .and_then(|status| status.effects.first())
+            .expect("Neighbour did not catch fire");
+        assert!(matches!(caught.status, StatusType::Burn { damage: 1 }));
+        assert_eq!(caught.duration, 19 / 2);
+        assert!(statuses.get(distant).is_none());
+        assert!(statuses.get(unscheduled).is_none());
+    }
+
+    fn actor(ecs: &mut World, current: i32, delta: i32, effects: Vec<StatusEffect>) -> Entity {
+        ecs.create_entity()
+            .with(Position { x: 1, y: 1 })
+            .with(Schedulable { current, delta })
+            .with(StatusEffects { effects })
+            .build()
+    }
+
+    #[test]
+    fn prediction_matches_the_scheduler() {
+        let (mut ecs, slowed) = world(StatusType::Slow { amount: 50 });
+        let plain = actor(&mut ecs, 10, 4, Vec::new());
+        let stunned = actor(
+            &mut ecs,
+            0,
+            3,
+            vec![StatusEffect {
+                status: StatusType::Stun,
+                duration: 3,
+            }],
+        );
+        let acting = actor(&mut ecs, -2, 6, Vec::new());
+        ecs.write_storage::<CanActFlag>()
+            .insert(acting, CanActFlag { is_reaction: false })
+            .expect("Failed to insert CanActFlag");
+
+        let actors = [slowed, plain, stunned, acting];
+        let predicted = predict_turn_order(&ecs, &actors, 12);
+
+        // every turn is spent waiting, the same as the prediction assumes
+        let mut actual = Vec::new();
+        let mut tick = 0;
+        while actual.len() < predicted.len() {
+            let up: Vec<Entity> = (&ecs.entities(), &ecs.read_storage::<CanActFlag>())
+                .join()
+                .map(|(ent, _)| ent)
+                .collect();
+            for ent in up {
+                actual.push((ent, tick));
+                ecs.write_storage::<CanActFlag>().remove(ent);
+                let statuses = ecs.read_storage::<StatusEffects>();
+                ecs.write_storage::<Schedulable>()
+                    .get_mut(ent)
+                    .expect("Actor lost its Schedulable")
+                    .charge(WAIT_COST, statuses.get(ent));
+            }
+
+            tick += 1;
+            *ecs.write_resource::<RunState>() = RunState::Running;
+            TurnSystem.run_now(&ecs);
+        }
+
+        actual.truncate(predicted.len());
+        assert_eq!(predicted.len(), 12);
+        assert_eq!(predicted, actual);
+        assert!(!predicted.contains(&(stunned, 1)));
     }
 }
@@ -26,6 +26,8 @@ impl<'a> System<'a> for VisibilitySystem {
             viewshed
                 .visible
                 .retain(|p| p.x >= 0 && p.x < map.width && p.y >= 0 && p.y < map.height);
+            // field_of_view comes back in hash order, so sort it to keep seeded runs reproducible
+            viewshed.visible.sort_by_key(|p| (p.y, p.x));
             viewshed.dirty = false;
 
             match player.get(ent) {
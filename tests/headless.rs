@@ -0,0 +1,889 @@
+use rltk::{Point, Rect, VirtualKeyCode};
+use specs::prelude::*;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use tymerl::headless::{HeadlessRunner, StopReason};
+use tymerl::raws::{self, RawMaster};
+use tymerl::rng::GameRng;
+use tymerl::saveload;
+use tymerl::{
+    player, spawner, AttackIntent, CanActFlag, Faction, Health, Inventory, Map, Position, RunState,
+    Schedulable, Telegraph, TileType,
+};
+
+const SEED: u64 = 7;
+const MAX_STEPS: usize = 500;
+
+/// A generated world cleared down to the player, standing at `start` in one big empty room
+/// Everything a test needs is spawned into it afterwards with `spawn`
+fn arena(start: Point) -> World {
+    let raws = RawMaster::load("raws/raws.json").expect("Failed to load raws");
+    arena_with(raws, start)
+}
+
+fn arena_with(raws: RawMaster, start: Point) -> World {
+    let mut ecs =
+        spawner::build_world(raws, GameRng::new(SEED), 80, 50).expect("Failed to build world");
+
+    let player = *ecs.fetch::<Entity>();
+    let others: Vec<Entity> = ecs.entities().join().filter(|ent| *ent != player).collect();
+    ecs.delete_entities(&others)
+        .expect("Failed to clear the world");
+    ecs.maintain();
+
+    let mut map = Map::new(80, 50, 0);
+    map.build_room(Rect::with_size(1, 1, 77, 47));
+    map.set_blocked_tiles();
+    ecs.insert(map);
+
+    ecs.write_storage::<Position>()
+        .insert(
+            player,
+            Position {
+                x: start.x,
+                y: start.y,
+            },
+        )
+        .expect("Failed to move the player");
+
+    ecs
+}
+
+/// Spawn something with the raws, see raws::with_raws
+fn spawn<F>(ecs: &mut World, spawner: F) -> Entity
+where
+    F: FnOnce(&mut World, &RawMaster) -> Result<Entity, tymerl::raws::RawsError>,
+{
+    raws::with_raws(ecs, |raws, ecs| spawner(ecs, raws)).expect("Failed to spawn")
+}
+
+fn script(commands: &str) -> Vec<player::Command> {
+    player::parse_script(commands).expect("Failed to parse script")
+}
+
+fn health(ecs: &World, ent: Entity) -> Option<i32> {
+    ecs.read_storage::<Health>()
+        .get(ent)
+        .map(|health| health.current)
+}
+
+fn player_reacting(ecs: &World) -> bool {
+    ecs.read_storage::<CanActFlag>()
+        .get(*ecs.fetch::<Entity>())
+        .is_some_and(|flag| flag.is_reaction)
+}
+
+#[test]
+fn bump_attack_lowers_health() {
+    let mut ecs = arena(Point::new(10, 10));
+    let barrel = spawn(&mut ecs, |ecs, raws| {
+        spawner::explosive_barrel(ecs, raws, 11, 10)
+    });
+
+    let mut runner = HeadlessRunner::new(ecs);
+    let reason = runner.run_until(script("move:1,0"), MAX_STEPS, |ecs| {
+        health(ecs, barrel) != Some(2)
+    });
+
+    assert_eq!(reason, StopReason::ConditionMet);
+    assert_eq!(health(runner.world(), barrel), Some(1));
+}
+
+#[test]
+fn death_trigger_damages_neighbours() {
+    let mut ecs = arena(Point::new(10, 10));
+    let barrel = spawn(&mut ecs, |ecs, raws| {
+        spawner::explosive_barrel(ecs, raws, 11, 10)
+    });
+    let neighbour = spawn(&mut ecs, |ecs, raws| {
+        spawner::explosive_barrel(ecs, raws, 12, 10)
+    });
+    ecs.write_storage::<Health>()
+        .get_mut(barrel)
+        .expect("Barrel does not have Health")
+        .current = 1;
+
+    // the explosion catches the player too, who takes the hit
+    let mut runner = HeadlessRunner::new(ecs);
+    let reason = runner.run_until(script("move:1,0 pass"), MAX_STEPS, |ecs| {
+        health(ecs, neighbour) != Some(2)
+    });
+
+    assert_eq!(reason, StopReason::ConditionMet);
+    assert!(!runner.world().is_alive(barrel));
+    assert_eq!(health(runner.world(), neighbour), Some(1));
+}
+
+/// Let a lurker next to the player take a swing, stopping once the player has to react
+fn swung_at() -> (HeadlessRunner, Entity) {
+    let mut ecs = arena(Point::new(10, 10));
+    spawn(&mut ecs, |ecs, raws| {
+        spawner::monster(ecs, raws, "lurker", 11, 10)
+    });
+    let player = *ecs.fetch::<Entity>();
+
+    let mut runner = HeadlessRunner::new(ecs);
+    let reason = runner.run_until(script("wait wait wait"), MAX_STEPS, player_reacting);
+    assert_eq!(reason, StopReason::ConditionMet);
+    assert_eq!(health(runner.world(), player), Some(10));
+
+    // nothing moves on without an answer
+    let reason = runner.run_until(Vec::new(), MAX_STEPS, |_| false);
+    assert_eq!(reason, StopReason::OutOfCommands);
+    assert!(player_reacting(runner.world()));
+    assert_eq!(health(runner.world(), player), Some(10));
+
+    (runner, player)
+}
+
+#[test]
+fn reaction_pause_waits_for_pass() {
+    let (mut runner, player) = swung_at();
+
+    let reason = runner.run_until(script("pass"), MAX_STEPS, |ecs| {
+        health(ecs, player) != Some(10)
+    });
+
+    assert_eq!(reason, StopReason::ConditionMet);
+    assert_eq!(health(runner.world(), player), Some(9));
+}
+
+#[test]
+fn reaction_pause_waits_for_dodge() {
+    let (mut runner, player) = swung_at();
+
+    let dodged = |ecs: &World| {
+        ecs.read_storage::<Position>()
+            .get(player)
+            .map(|pos| pos.as_point())
+            == Some(Point::new(9, 10))
+    };
+    let reason = runner.run_until(script("dodge:-1,0"), MAX_STEPS, dodged);
+
+    // the claw was aimed at the tile, so it lands in the same pass the dodge moves the player
+    assert_eq!(reason, StopReason::ConditionMet);
+    assert!(!player_reacting(runner.world()));
+    assert_eq!(health(runner.world(), player), Some(10));
+}
+
+/// The game's raws with the windup of attack `id` changed, loaded into an arena where the player
+/// never has to act, so every step is one pass of the systems
+fn windup_arena(id: &str, windup: i32) -> World {
+    let contents = std::fs::read_to_string("raws/raws.json").expect("Failed to read raws");
+    let mut json: serde_json::Value = serde_json::from_str(&contents).expect("Invalid raws");
+    let attack = json["attacks"]
+        .as_array_mut()
+        .and_then(|attacks| attacks.iter_mut().find(|attack| attack["id"] == id))
+        .expect("No such attack in the raws");
+    attack["windup"] = windup.into();
+    let raws = RawMaster::parse(id, &json.to_string()).expect("Failed to parse raws");
+
+    let ecs = arena_with(raws, Point::new(10, 10));
+    let player = *ecs.fetch::<Entity>();
+    ecs.write_storage::<Schedulable>().remove(player);
+    ecs
+}
+
+fn dummy(ecs: &mut World, x: i32, y: i32) -> Entity {
+    ecs.create_entity()
+        .with(Position { x, y })
+        .with(Health {
+            current: 10,
+            max: 10,
+        })
+        .build()
+}
+
+#[test]
+fn windup_does_not_resolve_the_tick_it_is_declared() {
+    // the slam sped up to the shortest windup there is
+    let mut ecs = windup_arena("slam", 1);
+
+    let target = dummy(&mut ecs, 20, 20);
+    let attacker = ecs
+        .create_entity()
+        .with(Position { x: 19, y: 20 })
+        .with(AttackIntent {
+            loc: Point::new(20, 20),
+            attack: "slam".to_string(),
+        })
+        .build();
+
+    let mut runner = HeadlessRunner::new(ecs);
+    assert!(runner.step(&mut std::iter::empty()));
+    assert_eq!(health(runner.world(), target), Some(10));
+    assert!(runner
+        .world()
+        .read_storage::<Telegraph>()
+        .get(attacker)
+        .is_some());
+
+    assert!(runner.step(&mut std::iter::empty()));
+    assert_eq!(health(runner.world(), target), Some(7));
+    assert!(runner
+        .world()
+        .read_storage::<Telegraph>()
+        .get(attacker)
+        .is_none());
+}
+
+#[test]
+fn wound_up_projectile_hits_whoever_stepped_in_the_way() {
+    let mut ecs = windup_arena("bolt", 2);
+    let target = dummy(&mut ecs, 25, 20);
+    ecs.create_entity()
+        .with(Position { x: 20, y: 20 })
+        .with(AttackIntent {
+            loc: Point::new(25, 20),
+            attack: "bolt".to_string(),
+        })
+        .build();
+
+    let mut runner = HeadlessRunner::new(ecs);
+    assert!(runner.step(&mut std::iter::empty()));
+
+    // the line was clear when the bolt was aimed, but someone walks into it during the windup
+    let mut ecs = runner.into_world();
+    let blocker = dummy(&mut ecs, 22, 20);
+    let mut runner = HeadlessRunner::new(ecs);
+    let reason = runner.run_until(Vec::new(), MAX_STEPS, |ecs| {
+        ecs.read_storage::<Telegraph>().is_empty()
+    });
+
+    assert_eq!(reason, StopReason::ConditionMet);
+    assert_eq!(health(runner.world(), blocker), Some(9));
+    assert_eq!(health(runner.world(), target), Some(10));
+}
+
+/// Take `steps` steps of a run, waiting whenever the player is up and taking every hit
+fn run_for(runner: &mut HeadlessRunner, steps: usize) {
+    let mut commands = script("wait pass").into_iter().cycle();
+    for _ in 0..steps {
+        assert!(runner.step(&mut commands));
+    }
+}
+
+/// Everything that ends up in a save, boiled down to one number
+fn state_hash(runner: &HeadlessRunner) -> u64 {
+    let save = saveload::save_to_string(runner.world(), runner.tick()).expect("Failed to save");
+    let mut hasher = DefaultHasher::new();
+    save.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[test]
+fn saving_does_not_change_the_run() {
+    let run = || {
+        let raws = RawMaster::load("raws/raws.json").expect("Failed to load raws");
+        let ecs =
+            spawner::build_world(raws, GameRng::new(SEED), 80, 50).expect("Failed to build world");
+        HeadlessRunner::new(ecs)
+    };
+
+    let mut unsaved = run();
+    run_for(&mut unsaved, 100);
+    run_for(&mut unsaved, 100);
+
+    let mut saved = run();
+    run_for(&mut saved, 100);
+    saveload::save_to_string(saved.world(), saved.tick()).expect("Failed to save");
+    run_for(&mut saved, 100);
+
+    assert_eq!(state_hash(&saved), state_hash(&unsaved));
+}
+
+/// Where everything with a name stands, in an order that doesn't depend on entity ids
+fn placements(ecs: &World) -> Vec<(String, i32, i32)> {
+    let mut placed: Vec<(String, i32, i32)> = (
+        &ecs.read_storage::<tymerl::Name>(),
+        &ecs.read_storage::<Position>(),
+    )
+        .join()
+        .map(|(name, pos)| (name.name.clone(), pos.x, pos.y))
+        .collect();
+    placed.sort();
+    placed
+}
+
+fn draws(ecs: &World) -> Vec<u64> {
+    let mut rng = ecs.fetch_mut::<GameRng>();
+    (0..8).map(|_| rng.rand()).collect()
+}
+
+#[test]
+fn a_loaded_save_plays_out_like_the_original() {
+    let raws = || RawMaster::load("raws/raws.json").expect("Failed to load raws");
+    let ecs =
+        spawner::build_world(raws(), GameRng::new(SEED), 80, 50).expect("Failed to build world");
+    let mut original = HeadlessRunner::new(ecs);
+    run_for(&mut original, 100);
+
+    let save = saveload::save_to_string(original.world(), original.tick()).expect("Failed to save");
+    let (mut ecs, tick) = saveload::load_from_str(&save).expect("Failed to load");
+    ecs.insert(raws());
+    let mut loaded = HeadlessRunner::resume(ecs, tick);
+
+    run_for(&mut original, 100);
+    run_for(&mut loaded, 100);
+
+    assert_eq!(loaded.tick(), original.tick());
+    assert!(loaded.world().fetch::<Map>().tiles == original.world().fetch::<Map>().tiles);
+    assert_eq!(placements(loaded.world()), placements(original.world()));
+    assert_eq!(state_hash(&loaded), state_hash(&original));
+    assert_eq!(draws(loaded.world()), draws(original.world()));
+}
+
+#[test]
+fn swapping_with_an_ally_does_not_charge_it() {
+    let mut ecs = arena(Point::new(10, 10));
+    let hound = spawn(&mut ecs, |ecs, raws| {
+        spawner::monster(ecs, raws, "hound", 11, 10)
+    });
+    let player = *ecs.fetch::<Entity>();
+
+    // stepping onto rubble costs extra, which the ally would pay if it took the step itself
+    {
+        let mut map = ecs.fetch_mut::<Map>();
+        let rubble = map.get_index(10, 10);
+        map.tiles[rubble] = TileType::Rubble;
+    }
+
+    let mut runner = HeadlessRunner::new(ecs);
+    let reason = runner.run_until(Vec::new(), MAX_STEPS, |_| false);
+    assert_eq!(reason, StopReason::OutOfCommands);
+
+    let schedule = |ecs: &World| {
+        ecs.read_storage::<Schedulable>()
+            .get(hound)
+            .map(|sched| (sched.current, sched.delta))
+            .expect("Hound does not have a Schedulable")
+    };
+    let position = |ecs: &World, ent: Entity| {
+        ecs.read_storage::<Position>()
+            .get(ent)
+            .map(|pos| pos.as_point())
+    };
+    let (before, delta) = schedule(runner.world());
+
+    // the command, then one pass of the systems to carry it out
+    let mut commands = script("move:1,0").into_iter();
+    assert!(runner.step(&mut commands));
+    assert!(runner.step(&mut commands));
+
+    assert_eq!(position(runner.world(), player), Some(Point::new(11, 10)));
+    assert_eq!(position(runner.world(), hound), Some(Point::new(10, 10)));
+    assert_eq!(schedule(runner.world()).0, before - delta);
+}
+
+#[test]
+fn allies_are_not_swapped_around_corners() {
+    let mut ecs = arena(Point::new(10, 10));
+    let hound = spawn(&mut ecs, |ecs, raws| {
+        spawner::monster(ecs, raws, "hound", 11, 11)
+    });
+    let player = *ecs.fetch::<Entity>();
+    {
+        let mut map = ecs.fetch_mut::<Map>();
+        let corner = map.get_index(11, 10);
+        map.tiles[corner] = TileType::Wall;
+        map.set_blocked_tiles();
+    }
+
+    let mut runner = HeadlessRunner::new(ecs);
+    let reason = runner.run_until(Vec::new(), MAX_STEPS, |_| false);
+    assert_eq!(reason, StopReason::OutOfCommands);
+
+    // the diagonal would cut the wall's corner, so the command is skipped
+    assert!(runner.step(&mut script("move:1,1").into_iter()));
+
+    let positions = runner.world().read_storage::<Position>();
+    let at = |ent: Entity| positions.get(ent).map(|pos| pos.as_point());
+    assert_eq!(at(player), Some(Point::new(10, 10)));
+    assert_eq!(at(hound), Some(Point::new(11, 11)));
+}
+
+#[test]
+fn fire_uses_the_ranged_ability() {
+    let mut ecs = arena(Point::new(10, 10));
+    let target = dummy(&mut ecs, 13, 10);
+
+    let mut runner = HeadlessRunner::new(ecs);
+    let reason = runner.run_until(script("fire:3,0"), MAX_STEPS, |ecs| {
+        health(ecs, target) != Some(10)
+    });
+
+    // the player's dagger throw
+    assert_eq!(reason, StopReason::ConditionMet);
+    assert_eq!(health(runner.world(), target), Some(8));
+}
+
+/// An arena where the player carries the items `ids`, ready to take their turn
+fn carrying(ids: &[&str]) -> (HeadlessRunner, Entity) {
+    let ecs = arena(Point::new(10, 10));
+    let player = *ecs.fetch::<Entity>();
+    ecs.write_storage::<Inventory>()
+        .get_mut(player)
+        .expect("Player does not have an Inventory")
+        .items = ids.iter().map(|id| id.to_string()).collect();
+
+    let mut runner = HeadlessRunner::new(ecs);
+    let reason = runner.run_until(Vec::new(), MAX_STEPS, |_| false);
+    assert_eq!(reason, StopReason::OutOfCommands);
+    (runner, player)
+}
+
+fn items(ecs: &World, ent: Entity) -> Vec<String> {
+    ecs.read_storage::<Inventory>()
+        .get(ent)
+        .map_or(Vec::new(), |inventory| inventory.items.clone())
+}
+
+#[test]
+fn potions_are_not_wasted_at_full_health() {
+    let (mut runner, player) = carrying(&["healing_potion"]);
+
+    assert!(runner.step(&mut script("item:1,0,0").into_iter()));
+    assert_eq!(items(runner.world(), player), vec!["healing_potion"]);
+    assert!(runner
+        .world()
+        .read_storage::<CanActFlag>()
+        .get(player)
+        .is_some());
+}
+
+#[test]
+fn items_that_cannot_be_dropped_stay_carried() {
+    let (mut runner, player) = carrying(&["no_such_item"]);
+
+    assert!(runner.step(&mut script("drop:1").into_iter()));
+    assert_eq!(items(runner.world(), player), vec!["no_such_item"]);
+}
+
+#[test]
+fn the_ninth_item_is_picked_with_i() {
+    let mut ids = vec!["bomb"; 8];
+    ids.push("healing_potion");
+    let (mut runner, player) = carrying(&ids);
+
+    runner.input(&press(VirtualKeyCode::I));
+    assert_eq!(*runner.world().fetch::<RunState>(), RunState::Inventory);
+    runner.input(&player::Input {
+        key: Some(VirtualKeyCode::I),
+        shift: true,
+        ..Default::default()
+    });
+    assert_eq!(*runner.world().fetch::<RunState>(), RunState::Running);
+    assert_eq!(items(runner.world(), player), vec!["bomb"; 8]);
+
+    // with fewer items, I just closes the screen
+    let reason = runner.run_until(Vec::new(), MAX_STEPS, |_| false);
+    assert_eq!(reason, StopReason::OutOfCommands);
+    runner.input(&press(VirtualKeyCode::I));
+    runner.input(&press(VirtualKeyCode::I));
+    assert_eq!(*runner.world().fetch::<RunState>(), RunState::AwaitingInput);
+}
+
+#[test]
+fn thrown_bomb_pauses_for_a_reaction() {
+    let (mut runner, player) = carrying(&["bomb"]);
+
+    // thrown right next to the player, who is caught in the blast too
+    let reason = runner.run_until(script("item:1,1,0"), MAX_STEPS, player_reacting);
+    assert_eq!(reason, StopReason::ConditionMet);
+    assert_eq!(health(runner.world(), player), Some(10));
+
+    let reason = runner.run_until(Vec::new(), MAX_STEPS, |_| false);
+    assert_eq!(reason, StopReason::OutOfCommands);
+    assert_eq!(health(runner.world(), player), Some(10));
+
+    let reason = runner.run_until(script("pass"), MAX_STEPS, |ecs| {
+        health(ecs, player) != Some(10)
+    });
+    assert_eq!(reason, StopReason::ConditionMet);
+    assert_eq!(health(runner.world(), player), Some(7));
+}
+
+fn goblin(ecs: &mut World, x: i32, y: i32) -> Entity {
+    let ent = dummy(ecs, x, y);
+    ecs.write_storage::<Faction>()
+        .insert(
+            ent,
+            Faction {
+                name: "goblins".to_string(),
+            },
+        )
+        .expect("Failed to give the dummy a faction");
+    ent
+}
+
+fn press(key: VirtualKeyCode) -> player::Input {
+    player::Input {
+        key: Some(key),
+        ..Default::default()
+    }
+}
+
+fn cursor(ecs: &World) -> Option<Point> {
+    match *ecs.fetch::<RunState>() {
+        RunState::Targeting { cursor, .. } => Some(cursor),
+        _ => None,
+    }
+}
+
+#[test]
+fn targeting_starts_on_the_closest_enemy_and_cycles() {
+    let mut ecs = arena(Point::new(10, 10));
+    let near = goblin(&mut ecs, 12, 10);
+    let far = goblin(&mut ecs, 10, 14);
+
+    let mut runner = HeadlessRunner::new(ecs);
+    let reason = runner.run_until(Vec::new(), MAX_STEPS, |_| false);
+    assert_eq!(reason, StopReason::OutOfCommands);
+
+    runner.input(&press(VirtualKeyCode::Key1));
+    assert_eq!(cursor(runner.world()), Some(Point::new(12, 10)));
+
+    // a frame where the mouse stays put leaves the cursor alone
+    runner.input(&player::Input::default());
+    assert_eq!(cursor(runner.world()), Some(Point::new(12, 10)));
+
+    runner.input(&press(VirtualKeyCode::Tab));
+    assert_eq!(cursor(runner.world()), Some(Point::new(10, 14)));
+    runner.input(&press(VirtualKeyCode::Tab));
+    assert_eq!(cursor(runner.world()), Some(Point::new(12, 10)));
+    runner.input(&press(VirtualKeyCode::Tab));
+    assert_eq!(cursor(runner.world()), Some(Point::new(10, 14)));
+
+    runner.input(&press(VirtualKeyCode::Return));
+    assert_eq!(*runner.world().fetch::<RunState>(), RunState::Running);
+
+    let reason = runner.run_until(Vec::new(), MAX_STEPS, |_| false);
+    assert_eq!(reason, StopReason::OutOfCommands);
+    assert_eq!(health(runner.world(), near), Some(10));
+    assert_eq!(health(runner.world(), far), Some(8));
+}
+
+#[test]
+fn targeting_follows_the_mouse_once_it_moves() {
+    let mut ecs = arena(Point::new(10, 10));
+    goblin(&mut ecs, 12, 10);
+    let clicked = goblin(&mut ecs, 13, 12);
+
+    let mut runner = HeadlessRunner::new(ecs);
+    let reason = runner.run_until(Vec::new(), MAX_STEPS, |_| false);
+    assert_eq!(reason, StopReason::OutOfCommands);
+
+    runner.input(&press(VirtualKeyCode::Key1));
+    runner.input(&player::Input {
+        mouse: Some(Point::new(13, 11)),
+        ..Default::default()
+    });
+    assert_eq!(cursor(runner.world()), Some(Point::new(13, 11)));
+
+    runner.input(&press(VirtualKeyCode::Down));
+    assert_eq!(cursor(runner.world()), Some(Point::new(13, 12)));
+
+    runner.input(&player::Input {
+        mouse: Some(Point::new(13, 12)),
+        click: true,
+        ..Default::default()
+    });
+    let reason = runner.run_until(Vec::new(), MAX_STEPS, |_| false);
+    assert_eq!(reason, StopReason::OutOfCommands);
+    assert_eq!(health(runner.world(), clicked), Some(8));
+}
+
+fn memory(ecs: &World, ent: Entity) -> Option<(Option<Point>, i32)> {
+    ecs.read_storage::<tymerl::Ai>()
+        .get(ent)
+        .map(|ai| (ai.last_seen, ai.search_turns))
+}
+
+#[test]
+fn lost_targets_are_searched_for_until_patience_runs_out() {
+    let mut ecs = arena(Point::new(10, 10));
+    let lurker = spawn(&mut ecs, |ecs, raws| {
+        spawner::monster(ecs, raws, "lurker", 14, 10)
+    });
+    let patience = ecs
+        .read_storage::<tymerl::Ai>()
+        .get(lurker)
+        .expect("Lurker has no Ai")
+        .patience;
+
+    let mut runner = HeadlessRunner::new(ecs);
+    let reason = runner.run_until(script("wait pass").into_iter().cycle(), MAX_STEPS, |ecs| {
+        memory(ecs, lurker).is_some_and(|(last_seen, _)| last_seen.is_some())
+    });
+    assert_eq!(reason, StopReason::ConditionMet);
+
+    // the player slips away, well out of the lurker's sight
+    let player = *runner.world().fetch::<Entity>();
+    runner
+        .world()
+        .write_storage::<Position>()
+        .insert(player, Position { x: 70, y: 40 })
+        .expect("Failed to move the player");
+
+    let mut seen = vec![memory(runner.world(), lurker).expect("Lurker has no Ai")];
+    let mut commands = script("wait pass").into_iter().cycle();
+    while seen
+        .last()
+        .is_some_and(|(last_seen, _)| last_seen.is_some())
+    {
+        assert!(seen.len() <= MAX_STEPS, "The lurker never gave up");
+        assert!(runner.step(&mut commands));
+        seen.push(memory(runner.world(), lurker).expect("Lurker has no Ai"));
+    }
+
+    // one turn off the search for every turn out of sight, until there are none left
+    seen.dedup();
+    let turns: Vec<i32> = seen.iter().map(|(_, turns)| *turns).collect();
+    assert_eq!(turns, (0..=patience).rev().collect::<Vec<i32>>());
+    assert_eq!(seen.last(), Some(&(None, 0)));
+}
+
+#[test]
+fn hostile_factions_fight_each_other() {
+    let mut ecs = arena(Point::new(10, 10));
+    let brute = spawn(&mut ecs, |ecs, raws| {
+        spawner::monster(ecs, raws, "brute", 50, 30)
+    });
+    let sentry = spawn(&mut ecs, |ecs, raws| {
+        spawner::monster(ecs, raws, "sentry", 53, 30)
+    });
+    let player = *ecs.fetch::<Entity>();
+
+    let mut runner = HeadlessRunner::new(ecs);
+    let reason = runner.run_until(script("wait pass").into_iter().cycle(), MAX_STEPS, |ecs| {
+        health(ecs, brute) < Some(8) || health(ecs, sentry) < Some(6)
+    });
+
+    // neither of them is anywhere near the player, so they can only be fighting each other
+    assert_eq!(reason, StopReason::ConditionMet);
+    assert_eq!(health(runner.world(), player), Some(10));
+}
+
+fn position(ecs: &World, ent: Entity) -> Option<Point> {
+    ecs.read_storage::<Position>()
+        .get(ent)
+        .map(|pos| pos.as_point())
+}
+
+#[test]
+fn behaviors_are_picked_by_priority() {
+    let run = |current: i32| {
+        let mut ecs = arena(Point::new(10, 10));
+        let lurker = spawn(&mut ecs, |ecs, raws| {
+            spawner::monster(ecs, raws, "lurker", 13, 10)
+        });
+        ecs.write_storage::<Health>()
+            .get_mut(lurker)
+            .expect("Lurker has no Health")
+            .current = current;
+
+        let mut runner = HeadlessRunner::new(ecs);
+        let reason = runner.run_until(script("wait pass").into_iter().cycle(), MAX_STEPS, |ecs| {
+            position(ecs, lurker) != Some(Point::new(13, 10))
+        });
+        assert_eq!(reason, StopReason::ConditionMet);
+        position(runner.world(), lurker).expect("Lurker is gone")
+    };
+
+    // a healthy lurker closes in, a badly hurt one runs before doing anything else
+    assert!(run(5).x < 13);
+    assert!(run(1).x > 13);
+}
+
+/// A generated run where the player is up, standing on the stairs down
+fn on_the_stairs_down() -> HeadlessRunner {
+    let raws = RawMaster::load("raws/raws.json").expect("Failed to load raws");
+    let ecs =
+        spawner::build_world(raws, GameRng::new(SEED), 80, 50).expect("Failed to build world");
+    let mut runner = HeadlessRunner::new(ecs);
+    let reason = runner.run_until(Vec::new(), MAX_STEPS, |_| false);
+    assert_eq!(reason, StopReason::OutOfCommands);
+
+    let stairs = runner
+        .world()
+        .fetch::<Map>()
+        .find_tile(TileType::DownStairs)
+        .expect("The level has no stairs down");
+    let player = *runner.world().fetch::<Entity>();
+    runner
+        .world()
+        .write_storage::<Position>()
+        .insert(
+            player,
+            Position {
+                x: stairs.x,
+                y: stairs.y,
+            },
+        )
+        .expect("Failed to move the player");
+    runner
+}
+
+/// Where everything stays put while the player is away, leaving out the player and companions
+fn level_contents(ecs: &World) -> Vec<(String, i32, i32)> {
+    placements(ecs)
+        .into_iter()
+        .filter(|(name, _, _)| name != "Player" && name != "Hound")
+        .collect()
+}
+
+#[test]
+fn going_back_up_restores_the_level_left_behind() {
+    let mut runner = on_the_stairs_down();
+    let tiles = runner.world().fetch::<Map>().tiles.clone();
+    let contents = level_contents(runner.world());
+    assert!(!contents.is_empty());
+
+    assert!(runner.step(&mut script("descend").into_iter()));
+    assert_eq!(runner.world().fetch::<Map>().depth, 1);
+    let reason = runner.run_until(Vec::new(), MAX_STEPS, |_| false);
+    assert_eq!(reason, StopReason::OutOfCommands);
+
+    // the player arrives on the stairs up, so they can go straight back
+    assert!(runner.step(&mut script("ascend").into_iter()));
+    {
+        let map = runner.world().fetch::<Map>();
+        assert_eq!(map.depth, 0);
+        assert!(map.tiles == tiles);
+    }
+    assert_eq!(level_contents(runner.world()), contents);
+}
+
+/// An arena with some of its tiles changed, run until the player is up
+fn arena_with_tiles(tiles: &[(i32, i32, TileType)]) -> HeadlessRunner {
+    let ecs = arena(Point::new(10, 10));
+    {
+        let mut map = ecs.fetch_mut::<Map>();
+        for (x, y, tile) in tiles {
+            let index = map.get_index(*x, *y);
+            map.tiles[index] = *tile;
+        }
+        map.set_blocked_tiles();
+    }
+
+    let mut runner = HeadlessRunner::new(ecs);
+    let reason = runner.run_until(Vec::new(), MAX_STEPS, |_| false);
+    assert_eq!(reason, StopReason::OutOfCommands);
+    runner
+}
+
+fn player_at(ecs: &World) -> Option<Point> {
+    position(ecs, *ecs.fetch::<Entity>())
+}
+
+fn tile_at(ecs: &World, x: i32, y: i32) -> TileType {
+    let map = ecs.fetch::<Map>();
+    map.tiles[map.get_index(x, y)]
+}
+
+#[test]
+fn doors_open_when_bumped_while_water_and_chasms_block() {
+    let mut runner = arena_with_tiles(&[
+        (11, 10, TileType::DoorClosed),
+        (10, 11, TileType::DeepWater),
+        (9, 10, TileType::Chasm),
+    ]);
+
+    // stepping into water or a chasm is refused without spending the turn
+    for command in ["move:0,1", "move:-1,0"] {
+        assert!(runner.step(&mut script(command).into_iter()));
+        assert_eq!(*runner.world().fetch::<RunState>(), RunState::AwaitingInput);
+        assert_eq!(player_at(runner.world()), Some(Point::new(10, 10)));
+    }
+
+    let reason = runner.run_until(script("move:1,0"), MAX_STEPS, |_| false);
+    assert_eq!(reason, StopReason::OutOfCommands);
+    assert!(tile_at(runner.world(), 11, 10) == TileType::DoorOpen);
+    assert_eq!(player_at(runner.world()), Some(Point::new(10, 10)));
+
+    let reason = runner.run_until(script("move:1,0"), MAX_STEPS, |_| false);
+    assert_eq!(reason, StopReason::OutOfCommands);
+    assert_eq!(player_at(runner.world()), Some(Point::new(11, 10)));
+}
+
+#[test]
+fn rubble_takes_longer_to_cross() {
+    // how many ticks go by before the player is up again after stepping onto `tile`
+    let wait_after_step = |tile: TileType| {
+        let mut runner = arena_with_tiles(&[(11, 10, tile)]);
+        let before = runner.tick();
+        let reason = runner.run_until(script("move:1,0"), MAX_STEPS, |_| false);
+        assert_eq!(reason, StopReason::OutOfCommands);
+        assert_eq!(player_at(runner.world()), Some(Point::new(11, 10)));
+        runner.tick() - before
+    };
+
+    // the player's Schedulable::delta, see spawner::player
+    let delta = 4;
+    assert_eq!(
+        wait_after_step(TileType::Rubble) - wait_after_step(TileType::Floor),
+        TileType::Rubble.move_cost() / delta
+    );
+}
+
+/// Whether the player gets from (10, 10) to (11, 9) in one diagonal step past `walls`
+fn steps_diagonally_past(walls: &[(i32, i32)], cut_corners: bool) -> bool {
+    let tiles: Vec<(i32, i32, TileType)> = walls
+        .iter()
+        .map(|(x, y)| (*x, *y, TileType::Wall))
+        .collect();
+    let mut runner = arena_with_tiles(&tiles);
+    runner.world().fetch_mut::<Map>().move_rules.cut_corners = cut_corners;
+
+    let reason = runner.run_until(script("move:1,-1"), MAX_STEPS, |_| false);
+    assert_eq!(reason, StopReason::OutOfCommands);
+    player_at(runner.world()) == Some(Point::new(11, 9))
+}
+
+#[test]
+fn diagonal_steps_follow_the_corner_rules() {
+    assert!(steps_diagonally_past(&[], false));
+
+    // a single wall is a corner that can only be cut if the rules allow it
+    assert!(!steps_diagonally_past(&[(11, 10)], false));
+    assert!(steps_diagonally_past(&[(11, 10)], true));
+
+    // squeezing between two walls is never allowed
+    assert!(!steps_diagonally_past(&[(11, 10), (10, 9)], true));
+}
+
+fn energy(ecs: &World) -> i32 {
+    ecs.read_storage::<Schedulable>()
+        .get(*ecs.fetch::<Entity>())
+        .map_or(0, |sched| sched.current)
+}
+
+/// What `command` charges the player's scheduler, with a dummy to punch standing east of them
+fn cost_of(command: &str) -> i32 {
+    let mut ecs = arena(Point::new(10, 10));
+    let target = dummy(&mut ecs, 11, 10);
+    ecs.write_storage::<tymerl::BlocksTile>()
+        .insert(target, tymerl::BlocksTile)
+        .expect("Failed to make the dummy block");
+    let mut runner = HeadlessRunner::new(ecs);
+    let reason = runner.run_until(Vec::new(), MAX_STEPS, |_| false);
+    assert_eq!(reason, StopReason::OutOfCommands);
+
+    let before = energy(runner.world());
+    assert!(runner.step(&mut script(command).into_iter()));
+    assert_eq!(*runner.world().fetch::<RunState>(), RunState::Running);
+    energy(runner.world()) - before
+}
+
+#[test]
+fn each_action_charges_its_own_cost() {
+    let raws = RawMaster::load("raws/raws.json").expect("Failed to load raws");
+
+    assert_eq!(cost_of("wait"), tymerl::WAIT_COST);
+    assert_eq!(cost_of("move:0,1"), tymerl::MOVE_COST);
+    assert_eq!(cost_of("move:1,0"), raws.attack_cost("punch"));
+    assert_eq!(cost_of("ability:3,1,0"), raws.attack_cost("sweep"));
+
+    // a quick punch comes around again sooner than waiting would
+    assert!(raws.attack_cost("punch") < tymerl::WAIT_COST);
+}